@@ -0,0 +1,96 @@
+use std::{fs::read, path::Path};
+
+use crate::{
+    ffmpeg::{ffmpeg_command, run_ffmpeg},
+    mediainfo::AudioTrack,
+};
+
+/// Peak level in dBFS of a track from `start` seconds until its end, as
+/// measured by the volumedetect filter. `None` when there is no audio to
+/// measure or ffmpeg fails.
+pub fn measure_peak(input_file: &Path, track: &AudioTrack, start: f64) -> Option<f64> {
+    let mut cmd = ffmpeg_command();
+    cmd.arg("-ss")
+        .arg(start.to_string())
+        .arg("-i")
+        .arg(input_file)
+        .args(vec![
+            "-map".to_owned(),
+            format!("0:a:{}", track.typeorder - 1),
+        ])
+        .args(vec!["-af", "volumedetect", "-f", "null", "-"]);
+
+    log::info!("Executing: {:?}", format!("{:?}", cmd).replace('\"', ""));
+    match run_ffmpeg(&mut cmd, &[]) {
+        Ok(output) => {
+            let log = String::from_utf8_lossy(&output.stderr);
+            if !output.status.success() {
+                log::warn!(
+                    "Failed to measure the loudness of track {} of file {}",
+                    track.typeorder,
+                    input_file.display()
+                );
+                log::trace!("FFMPEG error log: {}", log);
+                return None;
+            }
+            log.lines()
+                .find_map(|line| line.split_once("max_volume:"))
+                .and_then(|(_, volume)| volume.trim().trim_end_matches("dB").trim().parse().ok())
+        }
+        Err(e) => {
+            log::debug!("{}", e);
+            panic!("Error measuring loudness, is FFMPEG installed to path?");
+        }
+    }
+}
+
+/// Decodes a track to mono 16-bit PCM at `sample_rate` Hz. The samples go
+/// through a temporary file, as [`run_ffmpeg`] reads the progress of ffmpeg
+/// from stdout.
+pub fn decode_pcm(
+    input_file: &Path,
+    track: &AudioTrack,
+    sample_rate: u32,
+) -> Result<Vec<i16>, String> {
+    let pcm_file = tempfile::Builder::new()
+        .suffix(".pcm")
+        .tempfile()
+        .expect("Unable to create temporary file");
+    let mut cmd = ffmpeg_command();
+    cmd.args(vec!["-v", "error", "-y", "-i"])
+        .arg(input_file)
+        .args(vec![
+            "-map".to_owned(),
+            format!("0:a:{}", track.typeorder - 1),
+        ])
+        .args(vec!["-ac", "1", "-ar"])
+        .arg(sample_rate.to_string())
+        .args(vec!["-f", "s16le"])
+        .arg(pcm_file.path());
+
+    log::info!("Executing: {:?}", format!("{:?}", cmd).replace('\"', ""));
+    match run_ffmpeg(&mut cmd, &[]) {
+        Ok(output) => {
+            if !output.status.success() {
+                log::trace!(
+                    "FFMPEG error log: {}",
+                    String::from_utf8(output.stderr).unwrap()
+                );
+                return Err(format!(
+                    "Failed to decode track {} of file {}",
+                    track.typeorder,
+                    input_file.display()
+                ));
+            }
+            Ok(read(pcm_file.path())
+                .expect("Unable to read decoded track")
+                .chunks_exact(2)
+                .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+                .collect())
+        }
+        Err(e) => {
+            log::debug!("{}", e);
+            panic!("Error decoding track, is FFMPEG installed to path?");
+        }
+    }
+}
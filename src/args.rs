@@ -3,8 +3,20 @@ use std::{ffi::OsString, path::PathBuf};
 use clap::{Parser, Subcommand};
 use clap_verbosity_flag::{Verbosity, WarnLevel};
 
+use crate::codec::CodecPolicy;
+use crate::export::{
+    conflict::OnConflict,
+    output::{LargePcm, OutputFormat},
+    template::{NameTemplate, DEFAULT_NAME_TEMPLATE},
+    DEFAULT_VERIFY_TOLERANCE,
+};
+use crate::language::LanguageFilter;
+use crate::plan::{TailCheck, DEFAULT_SILENCE_THRESHOLD};
+use crate::select::Selection;
+use crate::sync::DEFAULT_MAX_OFFSET;
+
 #[derive(Parser, Debug)]
-#[clap(about = "This program requires mediainfo, ffmpeg, and ffprobe to be installed to PATH")]
+#[clap(about = "This program requires mediainfo and ffmpeg to be installed to PATH")]
 pub struct MkvAudurArgs {
     #[command(flatten)]
     pub verbose: Verbosity<WarnLevel>,
@@ -14,8 +26,20 @@ pub struct MkvAudurArgs {
     pub treshold: f64,
 
     #[clap(short, long)]
-    /// Only select tracks with this language code
-    pub language: Option<String>,
+    /// Only select tracks with these comma-separated languages, e.g. `jpn,en-US`,
+    /// or leave out languages with a leading `!`, e.g. `!eng`. ISO 639-1, ISO 639-2
+    /// and BCP 47 codes and English names all match; tracks without a language are `und`
+    pub language: Option<LanguageFilter>,
+
+    #[clap(long)]
+    /// Only select tracks matching this expression, e.g.
+    /// `lang in (jpn, eng) and codec != "AC-3" and channels >= 6 and not commentary`.
+    /// Fields: id, typeorder, lang, title, codec, profile, channels, sampling_rate,
+    /// bit_depth, duration and diff (track minus video duration in seconds), compared
+    /// with = != < <= > >=, `in (…)` or the regex operators ~ and !~.
+    /// Flags: default, forced, commentary, hearing_impaired, visual_impaired, original.
+    /// Combine with and, or, not and parentheses
+    pub select: Option<Selection>,
 
     #[clap(short, long)]
     /// Set a custom output directory
@@ -29,12 +53,89 @@ pub struct MkvAudurArgs {
     /// Path to use the video track duration of other mkv file(s)
     pub reference: Option<PathBuf>,
 
+    #[clap(long, value_enum, default_value_t = TailCheck::Warn)]
+    /// What to do when the end that would be trimmed off a track is not silent
+    pub tail_check: TailCheck,
+
+    #[clap(long, default_value_t = DEFAULT_SILENCE_THRESHOLD, allow_negative_numbers = true)]
+    /// Peak level in dBFS above which the end of a track is not considered silent
+    pub silence_threshold: f64,
+
+    #[clap(long)]
+    /// Time-stretch tracks that drift out of sync against an audio track of the reference file (or of the input file without --reference) at the drift rate sync-check fits, then trim or pad the rest. Tracks without drift are only trimmed or padded
+    pub stretch: bool,
+
+    #[clap(long, default_value_t = 1)]
+    /// Audio track (1 for the first audio track) that --stretch measures drift against
+    pub stretch_against: i64,
+
+    #[clap(long)]
+    /// Undo speed changes between 23.976, 24 and 25 fps (e.g. PAL speed-up) before equalizing the duration
+    pub speed_correct: bool,
+
+    #[clap(long)]
+    /// Keep the pitch of tracks when changing their speed with --stretch or --speed-correct
+    pub preserve_pitch: bool,
+
+    #[clap(long, value_name = "FORMAT=POLICY")]
+    /// How lossless tracks of a format are re-encoded: keep (same codec), flac, pcm, or core (extract the lossy core of hybrid tracks such as DTS-HD MA). TrueHD, MLP and DTS-HD MA default to flac, e.g. --lossless-policy dts=core
+    pub lossless_policy: Vec<CodecPolicy>,
+
+    #[clap(long, value_enum, default_value_t = OutputFormat::Elementary)]
+    /// How exported tracks are stored
+    pub output_format: OutputFormat,
+
+    #[clap(long, default_value = DEFAULT_NAME_TEMPLATE)]
+    /// Filename of exported tracks relative to the output directory, may contain subdirectories. Placeholders: {stem} {id} {typeorder} {lang} {LANG} {title} {format} {profile} {compression} {channels} {layout} {sampling_rate} {bit_depth} {bit_rate} {default} {forced} {duration} {delay} {ext}, with an optional zero-padded width like {typeorder:02}
+    pub name_template: NameTemplate,
+
+    #[clap(long)]
+    /// Add a MKVAUDUR tag to exported tracks that records what was changed, e.g. "trimmed from 7201.344 s to 7200.000 s"
+    pub tag_changes: bool,
+
+    #[clap(long, value_enum, default_value_t = LargePcm::Rf64)]
+    /// Container of PCM tracks larger than the 4 GiB a WAV file can hold
+    pub large_pcm: LargePcm,
+
+    #[clap(short, long, default_value_t = 1)]
+    /// Number of files to probe and ffmpeg exports to run concurrently. Tracks of a file that are copied, cut or padded share one ffmpeg export.
+    pub jobs: usize,
+
+    #[clap(long, default_value_t = DEFAULT_VERIFY_TOLERANCE)]
+    /// Maximum difference in seconds between the duration of an exported track and its planned duration
+    pub tolerance: f64,
+
+    #[clap(long)]
+    /// Do not re-probe exported tracks to verify their duration and format
+    pub no_verify: bool,
+
+    #[clap(long)]
+    /// Decode lossless tracks after export and check that only the appended silence or removed end differs from the source
+    pub verify_content: bool,
+
+    #[clap(long, value_enum, default_value_t = OnConflict::Overwrite)]
+    /// What to do when an output file already exists or several tracks would be exported to the same file
+    pub on_conflict: OnConflict,
+
+    #[clap(long)]
+    /// Move existing output files aside to <name>.bak instead of replacing them
+    pub backup: bool,
+
+    #[clap(long)]
+    /// Directory for temporary files, instead of the system temp directory
+    pub temp_dir: Option<PathBuf>,
+
+    #[clap(long)]
+    /// Do not check before exporting that the output and temp directories have enough free space
+    pub no_space_check: bool,
+
     #[command(subcommand)]
     /// Modes: "display" to list track durations or "export" to trim and export tracks
     pub mode: OperationMode,
 
-    /// Filepath, either a MKV file or directory with MKV files in it
-    pub filepath: PathBuf,
+    /// Filepath, either a MKV file or directory with MKV files in it. Required
+    /// except for apply
+    pub filepath: Option<PathBuf>,
 }
 
 #[derive(Debug, Subcommand)]
@@ -44,4 +145,32 @@ pub enum OperationMode {
 
     /// Export (trimmed) tracks
     Export,
+
+    /// Print the export plan as JSON
+    Plan,
+
+    /// Export tracks as described by a JSON export plan
+    Apply {
+        /// Path to the JSON export plan
+        plan: PathBuf,
+    },
+
+    /// Estimate the offset between audio tracks by cross-correlating them
+    SyncCheck {
+        #[clap(long, default_value_t = 1)]
+        /// Audio track (1 for the first audio track) of the reference file, or of the input file without --reference, to compare with
+        against: i64,
+
+        #[clap(long, default_value_t = DEFAULT_MAX_OFFSET, value_parser = parse_max_offset)]
+        /// Largest offset in seconds to search for
+        max_offset: f64,
+    },
+}
+
+fn parse_max_offset(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(max_offset) if max_offset.is_finite() && max_offset > 0.0 => Ok(max_offset),
+        Ok(_) => Err("must be a positive number of seconds".to_owned()),
+        Err(e) => Err(e.to_string()),
+    }
 }
@@ -0,0 +1,526 @@
+use std::str::FromStr;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{mediainfo::AudioTrack, plan::Strategy};
+
+/// How lossless tracks are encoded when they can not be stream copied
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum LosslessPolicy {
+    /// Re-encode with the ffmpeg encoder of the codec itself
+    #[default]
+    Keep,
+    /// Transcode to FLAC at the same bit depth
+    Flac,
+    /// Extract the lossy core of hybrid tracks, like the DTS core of DTS-HD MA.
+    /// Other lossless tracks use the default policy of their codec.
+    Core,
+    /// Decode to PCM at the same bit depth
+    Pcm,
+}
+
+/// How a track is compressed, which decides whether it can be stream copied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Lossy,
+    Lossless,
+    /// A lossless extension on top of a lossy core that decoders without
+    /// support for the extension play
+    Hybrid,
+}
+
+/// Lossy core embedded in a hybrid stream
+#[derive(Debug)]
+pub struct Core {
+    /// MediaInfo `Format` of the core
+    pub format: &'static str,
+    /// ffmpeg bitstream filter that extracts the core, if there is one
+    pub bitstream_filter: Option<&'static str>,
+}
+
+/// What mkvaudur knows about an audio format: how to store it in an
+/// elementary file and how its duration can be changed
+#[derive(Debug)]
+pub struct Codec {
+    /// MediaInfo `Format` values of the codec
+    pub formats: &'static [&'static str],
+    /// Part of MediaInfo `Format_Profile`, for formats that cover several
+    /// codecs
+    pub profile: Option<&'static str>,
+    /// Part of MediaInfo `Format_AdditionalFeatures` that tells the codec
+    /// apart from others with the same format
+    pub additional_feature: Option<&'static str>,
+    /// Extension of elementary files, without the leading dot
+    pub extension: &'static str,
+    /// ffmpeg muxer for elementary files
+    pub muxer: &'static str,
+    /// ffmpeg encoder, used to generate silence in the same format
+    pub encoder: &'static str,
+    pub lossless: bool,
+    /// Default policy for re-encoding lossless tracks of this codec
+    pub lossless_policy: LosslessPolicy,
+    /// Lossy core of hybrid tracks
+    pub core: Option<Core>,
+    /// Samples per frame, `None` when the frame size varies
+    pub frame_size: Option<u32>,
+    /// Strategies that can trim the track, in order of preference
+    pub trim_strategies: &'static [Strategy],
+    /// Strategies that can pad the track, in order of preference
+    pub pad_strategies: &'static [Strategy],
+}
+
+/// Stream copied frames of lossy codecs can be joined with encoded silence,
+/// which avoids re-encoding the whole track
+const LOSSY_PAD: &[Strategy] = &[Strategy::Concat, Strategy::Apad];
+const LOSSLESS_PAD: &[Strategy] = &[Strategy::Apad];
+const CUT: &[Strategy] = &[Strategy::Cut];
+
+/// DTS-HD MA, of which ffmpeg can only encode the lossy core
+const DTS_HD_MA: Codec = Codec {
+    formats: &["DTS"],
+    profile: None,
+    additional_feature: Some("XLL"),
+    extension: "dts",
+    muxer: "dts",
+    encoder: "dca",
+    lossless: true,
+    lossless_policy: LosslessPolicy::Flac,
+    core: Some(Core {
+        format: "DTS",
+        bitstream_filter: Some("dca_core"),
+    }),
+    frame_size: Some(512),
+    trim_strategies: CUT,
+    pad_strategies: LOSSLESS_PAD,
+};
+
+pub const CODECS: &[Codec] = &[
+    Codec {
+        formats: &["AAC"],
+        profile: None,
+        additional_feature: None,
+        extension: "aac",
+        muxer: "adts",
+        encoder: "aac",
+        lossless: false,
+        lossless_policy: LosslessPolicy::Keep,
+        core: None,
+        frame_size: Some(1024),
+        trim_strategies: CUT,
+        pad_strategies: LOSSY_PAD,
+    },
+    Codec {
+        formats: &["AC-3"],
+        profile: None,
+        additional_feature: None,
+        extension: "ac3",
+        muxer: "ac3",
+        encoder: "ac3",
+        lossless: false,
+        lossless_policy: LosslessPolicy::Keep,
+        core: None,
+        frame_size: Some(1536),
+        trim_strategies: CUT,
+        pad_strategies: LOSSY_PAD,
+    },
+    Codec {
+        formats: &["E-AC-3"],
+        profile: None,
+        additional_feature: None,
+        extension: "eac3",
+        muxer: "eac3",
+        encoder: "eac3",
+        lossless: false,
+        lossless_policy: LosslessPolicy::Keep,
+        core: None,
+        frame_size: Some(1536),
+        trim_strategies: CUT,
+        pad_strategies: LOSSY_PAD,
+    },
+    DTS_HD_MA,
+    // Older MediaInfo versions report DTS-HD MA by its profile "MA / Core"
+    Codec {
+        profile: Some("MA"),
+        additional_feature: None,
+        ..DTS_HD_MA
+    },
+    Codec {
+        formats: &["DTS", "DTS-HD"],
+        profile: None,
+        additional_feature: None,
+        extension: "dts",
+        muxer: "dts",
+        encoder: "dca",
+        lossless: false,
+        lossless_policy: LosslessPolicy::Keep,
+        core: None,
+        frame_size: Some(512),
+        trim_strategies: CUT,
+        pad_strategies: LOSSY_PAD,
+    },
+    // The TrueHD encoder of ffmpeg is experimental, and ffmpeg has no way to
+    // extract the AC-3 core that TrueHD tracks on Blu-ray carry
+    Codec {
+        formats: &["TrueHD", "MLP FBA"],
+        profile: None,
+        additional_feature: None,
+        extension: "thd",
+        muxer: "truehd",
+        encoder: "truehd",
+        lossless: true,
+        lossless_policy: LosslessPolicy::Flac,
+        core: Some(Core {
+            format: "AC-3",
+            bitstream_filter: None,
+        }),
+        frame_size: Some(40),
+        trim_strategies: CUT,
+        pad_strategies: LOSSLESS_PAD,
+    },
+    Codec {
+        formats: &["MLP"],
+        profile: None,
+        additional_feature: None,
+        extension: "mlp",
+        muxer: "mlp",
+        encoder: "mlp",
+        lossless: true,
+        lossless_policy: LosslessPolicy::Flac,
+        core: None,
+        frame_size: Some(40),
+        trim_strategies: CUT,
+        pad_strategies: LOSSLESS_PAD,
+    },
+    Codec {
+        formats: &["FLAC"],
+        profile: None,
+        additional_feature: None,
+        extension: "flac",
+        muxer: "flac",
+        encoder: "flac",
+        lossless: true,
+        lossless_policy: LosslessPolicy::Keep,
+        core: None,
+        frame_size: None,
+        trim_strategies: CUT,
+        pad_strategies: LOSSLESS_PAD,
+    },
+    Codec {
+        formats: &["PCM"],
+        profile: None,
+        additional_feature: None,
+        extension: "wav",
+        muxer: "wav",
+        encoder: "pcm_s16le",
+        lossless: true,
+        lossless_policy: LosslessPolicy::Keep,
+        core: None,
+        frame_size: Some(1),
+        trim_strategies: CUT,
+        pad_strategies: LOSSLESS_PAD,
+    },
+    Codec {
+        formats: &["Opus"],
+        profile: None,
+        additional_feature: None,
+        extension: "opus",
+        muxer: "opus",
+        encoder: "libopus",
+        lossless: false,
+        lossless_policy: LosslessPolicy::Keep,
+        core: None,
+        frame_size: Some(960),
+        trim_strategies: CUT,
+        pad_strategies: LOSSY_PAD,
+    },
+    Codec {
+        formats: &["Vorbis"],
+        profile: None,
+        additional_feature: None,
+        extension: "ogg",
+        muxer: "ogg",
+        encoder: "libvorbis",
+        lossless: false,
+        lossless_policy: LosslessPolicy::Keep,
+        core: None,
+        frame_size: None,
+        trim_strategies: CUT,
+        pad_strategies: LOSSY_PAD,
+    },
+    Codec {
+        formats: &["MPEG Audio"],
+        profile: Some("Layer 3"),
+        additional_feature: None,
+        extension: "mp3",
+        muxer: "mp3",
+        encoder: "libmp3lame",
+        lossless: false,
+        lossless_policy: LosslessPolicy::Keep,
+        core: None,
+        frame_size: Some(1152),
+        trim_strategies: CUT,
+        pad_strategies: LOSSY_PAD,
+    },
+    Codec {
+        formats: &["MPEG Audio"],
+        profile: Some("Layer 2"),
+        additional_feature: None,
+        extension: "mp2",
+        muxer: "mp2",
+        encoder: "mp2",
+        lossless: false,
+        lossless_policy: LosslessPolicy::Keep,
+        core: None,
+        frame_size: Some(1152),
+        trim_strategies: CUT,
+        pad_strategies: LOSSY_PAD,
+    },
+    Codec {
+        formats: &["ALAC"],
+        profile: None,
+        additional_feature: None,
+        extension: "m4a",
+        muxer: "ipod",
+        encoder: "alac",
+        lossless: true,
+        lossless_policy: LosslessPolicy::Keep,
+        core: None,
+        frame_size: Some(4096),
+        trim_strategies: CUT,
+        pad_strategies: LOSSLESS_PAD,
+    },
+];
+
+/// Lossless policy for one codec, given on the command line as `FORMAT=POLICY`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodecPolicy {
+    /// MediaInfo format name, compared case insensitively
+    pub format: String,
+    pub policy: LosslessPolicy,
+}
+
+impl FromStr for CodecPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((format, policy)) = s.split_once('=') else {
+            return Err(format!("expected FORMAT=POLICY, got {}", s));
+        };
+        Ok(CodecPolicy {
+            format: format.trim().to_owned(),
+            policy: LosslessPolicy::from_str(policy.trim(), true)?,
+        })
+    }
+}
+
+impl Codec {
+    /// Looks up the codec of a track by its MediaInfo format, profile and
+    /// additional features
+    pub fn for_track(track: &AudioTrack) -> Option<&'static Codec> {
+        CODECS.iter().find(|codec| {
+            codec.formats.contains(&track.format.as_str())
+                && codec.profile.is_none_or(|profile| {
+                    track
+                        .format_profile
+                        .as_deref()
+                        .is_some_and(|profiles| profiles.split(" / ").any(|p| p == profile))
+                })
+                && codec.additional_feature.is_none_or(|feature| {
+                    track
+                        .additional_features
+                        .as_deref()
+                        .is_some_and(|features| features.split(' ').any(|f| f == feature))
+                })
+        })
+    }
+
+    fn for_format(format: &str) -> Option<&'static Codec> {
+        CODECS.iter().find(|codec| {
+            codec.formats.contains(&format)
+                && codec.profile.is_none()
+                && codec.additional_feature.is_none()
+        })
+    }
+}
+
+/// Classifies a track by its MediaInfo compression mode, which is
+/// "Lossless / Lossy" for hybrid tracks. Codecs of the registry with a core
+/// are hybrid whatever the compression mode says, as MediaInfo reports some
+/// of them as plain "Lossless". When MediaInfo does not report a known
+/// compression mode, the codec registry decides, and unknown codecs are
+/// treated as lossy so they are only ever stream copied.
+pub fn compression(track: &AudioTrack) -> Compression {
+    if Codec::for_track(track).is_some_and(|codec| codec.core.is_some()) {
+        return Compression::Hybrid;
+    }
+    match track.compression_mode.as_deref() {
+        Some("Lossy") => Compression::Lossy,
+        Some("Lossless") => Compression::Lossless,
+        Some(mode) if mode.contains("Lossless") && mode.contains("Lossy") => Compression::Hybrid,
+        mode => match Codec::for_track(track) {
+            Some(codec) if codec.lossless => Compression::Lossless,
+            Some(_) => Compression::Lossy,
+            None => {
+                log::debug!(
+                    "Unknown compression mode {:?} of {} track, treating it as lossy",
+                    mode,
+                    track.format
+                );
+                Compression::Lossy
+            }
+        },
+    }
+}
+
+/// Whether the exported track is encoded losslessly, which is the case for
+/// hybrid tracks unless their core is extracted
+pub fn encodes_lossless(track: &AudioTrack, policy: LosslessPolicy) -> bool {
+    match compression(track) {
+        Compression::Lossy => false,
+        Compression::Lossless => true,
+        Compression::Hybrid => policy != LosslessPolicy::Core,
+    }
+}
+
+/// Policy for a lossless track: the first override that names its format, or
+/// another format of the same codec, or else the default of its codec. Falls
+/// back to the default when the core of a track is asked for but can not be
+/// extracted.
+pub fn lossless_policy(track: &AudioTrack, overrides: &[CodecPolicy]) -> LosslessPolicy {
+    let codec = Codec::for_track(track);
+    let formats = codec.map_or(vec![track.format.as_str()], |codec| codec.formats.to_vec());
+    let default = match codec {
+        Some(codec) if codec.lossless => codec.lossless_policy,
+        // Re-encoding a hybrid track the registry does not know as lossless
+        // with its own encoder would only keep the lossy part
+        _ if compression(track) == Compression::Hybrid => LosslessPolicy::Flac,
+        Some(codec) => codec.lossless_policy,
+        None => LosslessPolicy::default(),
+    };
+    let policy = overrides
+        .iter()
+        .find(|o| formats.iter().any(|f| o.format.eq_ignore_ascii_case(f)))
+        .map_or(default, |o| o.policy);
+
+    if policy == LosslessPolicy::Core && core_filter(track, policy).is_none() {
+        if compression(track) == Compression::Hybrid {
+            log::warn!(
+                "Unable to extract the core of {} track {}, exporting the full stream",
+                track.format,
+                track.typeorder
+            );
+        }
+        return default;
+    }
+    policy
+}
+
+/// Bitstream filter that extracts the core of a hybrid track, when `policy`
+/// asks for it
+pub fn core_filter(track: &AudioTrack, policy: LosslessPolicy) -> Option<&'static str> {
+    match (policy, compression(track)) {
+        (LosslessPolicy::Core, Compression::Hybrid) => Codec::for_track(track)
+            .and_then(|codec| codec.core.as_ref())
+            .and_then(|core| core.bitstream_filter),
+        _ => None,
+    }
+}
+
+/// Codec of the exported file, which differs from the codec of the track
+/// when a lossless track is transcoded or the core is extracted
+pub fn output_codec(track: &AudioTrack, policy: LosslessPolicy) -> Option<&'static Codec> {
+    match (policy, compression(track)) {
+        (LosslessPolicy::Flac, Compression::Lossless | Compression::Hybrid) => {
+            Codec::for_format("FLAC")
+        }
+        (LosslessPolicy::Pcm, Compression::Lossless | Compression::Hybrid) => {
+            Codec::for_format("PCM")
+        }
+        (LosslessPolicy::Core, Compression::Hybrid) => Codec::for_track(track)
+            .and_then(|codec| codec.core.as_ref())
+            .and_then(|core| Codec::for_format(core.format)),
+        _ => Codec::for_track(track),
+    }
+}
+
+/// Encoder arguments that re-encode a lossless track without changing its
+/// bit depth. Empty for unknown codecs, which leaves the choice to ffmpeg.
+pub fn lossless_encoder_args(track: &AudioTrack, policy: LosslessPolicy) -> Vec<String> {
+    let Some(codec) = output_codec(track, policy) else {
+        return vec![];
+    };
+    let mut args = vec!["-c:a".to_owned()];
+    match (codec.formats[0], track.bit_depth) {
+        ("PCM", _) => args.push(pcm_encoder(track, codec.muxer)),
+        // These encoders store 24-bit audio as 32-bit samples
+        ("FLAC" | "ALAC", Some(bit_depth)) => {
+            let planar = match codec.encoder {
+                "alac" => "p",
+                _ => "",
+            };
+            let sample_fmt = match bit_depth > 16 {
+                true => "s32",
+                false => "s16",
+            };
+            args.extend([
+                codec.encoder.to_owned(),
+                "-sample_fmt".to_owned(),
+                format!("{}{}", sample_fmt, planar),
+                "-bits_per_raw_sample".to_owned(),
+                bit_depth.to_string(),
+            ]);
+        }
+        _ => args.extend([
+            codec.encoder.to_owned(),
+            "-strict".to_owned(),
+            "experimental".to_owned(),
+        ]),
+    }
+    args
+}
+
+/// PCM encoder with the bit depth, sign and endianness of the track. WAV only
+/// stores little endian samples.
+fn pcm_encoder(track: &AudioTrack, muxer: &str) -> String {
+    let bit_depth = track.bit_depth.unwrap_or(16);
+    let float = track
+        .format_profile
+        .as_deref()
+        .is_some_and(|profile| profile.starts_with("Float"));
+    let kind = match (float, track.sign.as_deref()) {
+        (true, _) => "f",
+        (false, Some("Unsigned")) => "u",
+        // 8-bit PCM is unsigned unless stated otherwise
+        (false, None) if bit_depth == 8 => "u",
+        (false, _) => "s",
+    };
+    if bit_depth == 8 {
+        return format!("pcm_{}8", kind);
+    }
+    let endianness = match (track.endianness.as_deref(), muxer) {
+        (Some("Big"), muxer) if muxer != "wav" => "be",
+        _ => "le",
+    };
+    format!("pcm_{}{}{}", kind, bit_depth, endianness)
+}
+
+/// Extension of the elementary file of a track, including the leading dot.
+/// Unknown formats fall back to the lowercased format name, without the
+/// characters that do not belong in an extension.
+pub fn get_audio_ext(track: &AudioTrack, policy: LosslessPolicy) -> String {
+    match output_codec(track, policy) {
+        Some(codec) => format!(".{}", codec.extension),
+        None => {
+            log::debug!("Unknown audio format {}", track.format);
+            let extension: String = track
+                .format
+                .to_lowercase()
+                .chars()
+                .filter(char::is_ascii_alphanumeric)
+                .collect();
+            format!(".{}", extension)
+        }
+    }
+}
@@ -1,43 +1,39 @@
-use std::path::Path;
+use crate::plan::{FilePlan, PlanOptions, Strategy, TrackAction};
 
-use serde_json::Value;
-
-use crate::TrackFilter;
-
-pub fn display(mkv_mediainfo: &Value, video_duration: f64, track_filter: &TrackFilter) {
+pub fn display(file_plan: &FilePlan, plan_options: &PlanOptions) {
     println!(
         "{} | Video Duration: {}",
-        Path::new(mkv_mediainfo["media"]["@ref"].as_str().unwrap())
-            .file_name()
-            .unwrap()
-            .to_str()
-            .unwrap(),
-        video_duration
+        file_plan.input.file_name().unwrap().to_str().unwrap(),
+        file_plan.video_duration
     );
-    let tracks = mkv_mediainfo["media"]["track"].as_array().unwrap();
-    for track in tracks {
-        if track["@type"] != "Audio" {
-            continue;
+    for track_plan in &file_plan.tracks {
+        let track = &track_plan.track;
+        print!(
+            "Track {} ({}): Duration: {} Difference: {}",
+            track.id,
+            track.language.as_deref().unwrap_or("und"),
+            track.duration,
+            track_plan.duration_difference(file_plan.video_duration)
+        );
+        if let Some(tail_peak) = track_plan.tail_peak {
+            print!(" Cut region peak: {:.1} dBFS", tail_peak);
+            if !track_plan.tail_is_silent(plan_options) {
+                print!(" (not silent)");
+            }
         }
-        let duration_difference: f64 =
-            track["Duration"].as_str().unwrap().parse::<f64>().unwrap() - video_duration;
-
-        let track_language = track["Language"].as_str();
-
-        if ((track_filter.language.is_none()
-            || (track_language.is_some()
-                && track_language.unwrap() == track_filter.language.as_ref().unwrap()))
-            && f64::abs(duration_difference) > track_filter.treshold)
-            || track_filter.process_all
-        {
-            println!(
-                "Track {} ({}): Duration: {} Difference: {}",
-                track["ID"].as_str().unwrap().parse::<i64>().unwrap(),
-                track_language.unwrap_or("und"),
-                track["Duration"].as_str().unwrap().parse::<f64>().unwrap(),
-                duration_difference
+        if let Some(speed_change) = track_plan.speed_change {
+            print!(
+                " Speed change: {:.3} fps audio on {:.3} fps video",
+                speed_change.audio_fps, speed_change.video_fps
             );
+            if !matches!(track_plan.strategy, Strategy::Stretch { .. }) {
+                print!(" (correct with --speed-correct)");
+            }
+        }
+        if track_plan.action == TrackAction::Skip {
+            print!(" [skipped]");
         }
+        println!();
     }
     println!()
 }
@@ -1,42 +1,77 @@
-use std::ffi::OsString;
+use std::fmt;
+use std::fs::{create_dir_all, metadata, remove_file, rename};
 use std::path::{Path, PathBuf};
-use std::process::Command;
 
-use serde_json::Value;
+use tempfile::{tempdir, tempdir_in, TempDir};
 
-use crate::TrackFilter;
+use crate::cancel::is_cancelled;
+use crate::codec::encodes_lossless;
+use crate::ffmpeg::{ffmpeg_command, run_ffmpeg};
+use crate::jobs::run_ordered;
+use crate::plan::{FilePlan, Strategy, TrackAction, TrackPlan};
+use crate::progress::{ProgressCallback, TrackProgress};
 
-use self::output::{create_track_filepath, get_codec_args, get_map_args};
-use super::export::{append::append_silence, trim::trim_silence};
+use self::conflict::{backup_existing, resolve_conflicts, OnConflict};
+use self::journal::Journal;
+use self::merge::merge_tracks;
+use self::output::{
+    create_partial_filepath, get_codec_args, get_filter_args, get_format_args, get_map_args,
+    get_metadata_args,
+};
+use self::single_pass::{export_single_pass, shares_input};
+use self::space::check_free_space;
+use self::verify::{verify_audio_content, verify_output};
+use super::export::{append::append_silence, stretch::stretch_track, trim::trim_silence};
 
 pub mod append;
-mod output;
+pub mod conflict;
+mod journal;
+pub mod merge;
+pub mod output;
+pub mod single_pass;
+pub mod space;
+pub mod stretch;
+pub mod template;
 pub mod trim;
+pub mod verify;
 
-fn export_unchanged(input_file: &PathBuf, track: &Value, output_dir: &Option<OsString>) {
-    let mut cmd = Command::new("ffmpeg");
+fn export_unchanged(
+    input_file: &PathBuf,
+    track_plan: &TrackPlan,
+    progress: &TrackProgress,
+) -> Result<(), String> {
+    let track = &track_plan.track;
+    let mut cmd = ffmpeg_command();
     cmd.arg("-y")
         .arg("-i")
         .arg(input_file)
         .args(get_map_args(track))
         .args(vec!["-map_chapters", "-1"])
-        .args(get_codec_args(track))
-        .arg(create_track_filepath(input_file, track, output_dir));
+        .args(get_filter_args(track, track_plan.lossless_policy, None))
+        .args(get_codec_args(track, track_plan.lossless_policy))
+        .args(get_metadata_args(track_plan))
+        .args(get_format_args(
+            track,
+            track_plan.lossless_policy,
+            track_plan.container,
+        ))
+        .arg(&track_plan.output);
 
     log::info!("Executing: {:?}", format!("{:?}", cmd).replace('\"', ""));
-    match cmd.output() {
+    match run_ffmpeg(&mut cmd, &[progress]) {
         Ok(output) => {
             if !output.status.success() {
-                log::error!(
-                    "Failed to export track {} of file {}",
-                    get_map_args(track)[1],
-                    input_file.display()
-                );
                 log::trace!(
                     "FFMPEG error log: {}",
                     String::from_utf8(output.stderr).unwrap()
                 );
+                return Err(format!(
+                    "Failed to export track {} of file {}",
+                    get_map_args(track)[1],
+                    input_file.display()
+                ));
             }
+            Ok(())
         }
         Err(e) => {
             log::debug!("{}", e);
@@ -45,41 +80,548 @@ fn export_unchanged(input_file: &PathBuf, track: &Value, output_dir: &Option<OsS
     }
 }
 
-pub fn export(
-    mkv_file: &PathBuf,
-    mkv_mediainfo: &Value,
-    video_duration: f64,
-    track_filter: &TrackFilter,
-    output_dir: &Option<OsString>,
-) {
-    println!(
-        "Processing file: {}",
-        Path::new(mkv_mediainfo["media"]["@ref"].as_str().unwrap())
-            .file_name()
-            .unwrap()
-            .to_str()
-            .unwrap()
-    );
-    let tracks = mkv_mediainfo["media"]["track"].as_array().unwrap();
-    for track in tracks {
-        if track["@type"] != "Audio" {
-            continue;
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrackStatus {
+    Exported,
+    /// Exported by an earlier run that was cancelled
+    AlreadyExported,
+    /// Not exported, see the action of the track plan
+    Skipped,
+    Failed(String),
+    Cancelled,
+}
+
+#[derive(Debug, Clone)]
+pub struct TrackReport {
+    pub typeorder: i64,
+    pub output: PathBuf,
+    pub status: TrackStatus,
+}
+
+/// Outcome of merging the tracks of a file into one Matroska file
+#[derive(Debug, Clone)]
+pub struct MergeReport {
+    pub output: PathBuf,
+    pub status: TrackStatus,
+}
+
+#[derive(Debug, Clone)]
+pub struct FileReport {
+    pub input: PathBuf,
+    pub tracks: Vec<TrackReport>,
+    pub merged: Option<MergeReport>,
+}
+
+impl fmt::Display for FileReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Processing file: {}",
+            self.input.file_name().unwrap().to_str().unwrap()
+        )?;
+        for track in &self.tracks {
+            match &track.status {
+                TrackStatus::Exported => writeln!(
+                    f,
+                    "Track {:02}: exported to {}",
+                    track.typeorder,
+                    track.output.display()
+                )?,
+                TrackStatus::AlreadyExported => writeln!(
+                    f,
+                    "Track {:02}: already exported to {}",
+                    track.typeorder,
+                    track.output.display()
+                )?,
+                TrackStatus::Failed(reason) => {
+                    writeln!(f, "Track {:02}: failed: {}", track.typeorder, reason)?
+                }
+                TrackStatus::Skipped => writeln!(f, "Track {:02}: skipped", track.typeorder)?,
+                TrackStatus::Cancelled => writeln!(f, "Track {:02}: cancelled", track.typeorder)?,
+            }
+        }
+        if let Some(merged) = &self.merged {
+            match &merged.status {
+                TrackStatus::Exported | TrackStatus::AlreadyExported => {
+                    writeln!(f, "Tracks merged into {}", merged.output.display())?
+                }
+                TrackStatus::Failed(reason) => writeln!(f, "Merging tracks failed: {}", reason)?,
+                TrackStatus::Skipped => writeln!(f, "Tracks not merged")?,
+                TrackStatus::Cancelled => writeln!(f, "Merging tracks cancelled")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+pub const DEFAULT_VERIFY_TOLERANCE: f64 = 0.1;
+
+#[derive(Clone)]
+pub struct ExportOptions {
+    /// Number of ffmpeg exports to run concurrently
+    pub jobs: usize,
+    /// Receives progress updates of the running ffmpeg processes
+    pub on_progress: Option<ProgressCallback>,
+    /// Maximum difference in seconds between the duration of an exported
+    /// track and its target duration. No verification is done when `None`.
+    pub verify_tolerance: Option<f64>,
+    /// Compare the decoded audio of lossless tracks with the source track
+    pub verify_content: bool,
+    pub on_conflict: OnConflict,
+    /// Move existing files aside to `<name>.bak` instead of replacing them
+    pub backup: bool,
+    /// Directory for temporary files, defaults to the system temp directory
+    pub temp_dir: Option<PathBuf>,
+    /// Abort before exporting when an output or the temp directory does not
+    /// have enough free space
+    pub space_check: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            jobs: 1,
+            on_progress: None,
+            verify_tolerance: Some(DEFAULT_VERIFY_TOLERANCE),
+            verify_content: false,
+            on_conflict: OnConflict::default(),
+            backup: false,
+            temp_dir: None,
+            space_check: true,
+        }
+    }
+}
+
+/// Status of a track that is not exported at all
+fn unexported_status(
+    input_file: &Path,
+    track_plan: &TrackPlan,
+    journal: &Journal,
+) -> Option<TrackStatus> {
+    if track_plan.action == TrackAction::Skip {
+        Some(TrackStatus::Skipped)
+    } else if journal.is_completed(input_file, track_plan) {
+        Some(TrackStatus::AlreadyExported)
+    } else if is_cancelled() {
+        Some(TrackStatus::Cancelled)
+    } else {
+        None
+    }
+}
+
+/// Temporary directory in `temp_dir`, or else in the system temp directory
+fn create_temp_dir(temp_dir: Option<&Path>) -> TempDir {
+    match temp_dir {
+        Some(temp_dir) => tempdir_in(temp_dir),
+        None => tempdir(),
+    }
+    .expect("Unable to create temporary directory")
+}
+
+fn run_strategy(
+    input_file: &PathBuf,
+    partial_plan: &TrackPlan,
+    progress: &TrackProgress,
+    options: &ExportOptions,
+) -> Result<(), String> {
+    match partial_plan.strategy {
+        Strategy::Copy => export_unchanged(input_file, partial_plan, progress),
+        Strategy::Cut => trim_silence(input_file, partial_plan, progress),
+        Strategy::Apad | Strategy::Concat => append_silence(
+            input_file,
+            partial_plan,
+            options.temp_dir.as_deref(),
+            progress,
+        ),
+        Strategy::Stretch {
+            tempo,
+            preserve_pitch,
+        } => stretch_track(input_file, partial_plan, tempo, preserve_pitch, progress),
+        Strategy::None => Ok(()),
+    }
+}
+
+/// Exports the tracks of one file. Several tracks share one ffmpeg process,
+/// see [`export_single_pass`], and are exported one by one when that fails.
+/// Reports are returned in the order of `track_plans`.
+fn export_tracks(
+    input_file: &PathBuf,
+    track_plans: &[&TrackPlan],
+    options: &ExportOptions,
+    journal: &Journal,
+) -> Vec<TrackReport> {
+    let statuses: Vec<Option<TrackStatus>> = track_plans
+        .iter()
+        .map(|track_plan| unexported_status(input_file, track_plan, journal))
+        .collect();
+    let pending: Vec<&TrackPlan> = track_plans
+        .iter()
+        .zip(&statuses)
+        .filter(|(_, status)| status.is_none())
+        .map(|(track_plan, _)| *track_plan)
+        .collect();
+
+    for track_plan in &pending {
+        if let Some(output_dir) = track_plan.output.parent() {
+            if create_dir_all(output_dir).is_err() {
+                panic!("Unable to create output directory {}", output_dir.display())
+            }
+        }
+    }
+
+    // ffmpeg writes to temporary files next to the outputs, which replace
+    // the outputs only once they are complete
+    let partial_plans: Vec<TrackPlan> = pending
+        .iter()
+        .map(|track_plan| TrackPlan {
+            output: create_partial_filepath(&track_plan.output),
+            ..(*track_plan).clone()
+        })
+        .collect();
+    let progress: Vec<TrackProgress> = pending
+        .iter()
+        .map(|track_plan| TrackProgress {
+            input: input_file,
+            typeorder: track_plan.track.typeorder,
+            duration: track_plan.target_duration,
+            callback: options.on_progress.as_ref(),
+        })
+        .collect();
+    let results: Vec<Result<(), String>> = match partial_plans.as_slice() {
+        [] => vec![],
+        [partial_plan] => vec![run_strategy(
+            input_file,
+            partial_plan,
+            &progress[0],
+            options,
+        )],
+        partial_plans => {
+            let progress: Vec<&TrackProgress> = progress.iter().collect();
+            match export_single_pass(input_file, partial_plans, &progress) {
+                Ok(()) => vec![Ok(()); partial_plans.len()],
+                Err(e) if is_cancelled() => vec![Err(e); partial_plans.len()],
+                // One track failing should not fail the others with it
+                Err(e) => {
+                    log::warn!("{}, exporting the tracks one by one", e);
+                    partial_plans
+                        .iter()
+                        .zip(progress)
+                        .map(|(partial_plan, progress)| {
+                            run_strategy(input_file, partial_plan, progress, options)
+                        })
+                        .collect()
+                }
+            }
+        }
+    };
+
+    let mut finished = pending
+        .iter()
+        .zip(partial_plans)
+        .zip(progress.iter().zip(results))
+        .map(|((track_plan, partial_plan), (progress, result))| {
+            finish_track(
+                input_file,
+                track_plan,
+                &partial_plan,
+                result,
+                progress,
+                options,
+                journal,
+            )
+        });
+    track_plans
+        .iter()
+        .zip(statuses)
+        .map(|(track_plan, status)| match status {
+            Some(status) => TrackReport {
+                typeorder: track_plan.track.typeorder,
+                output: track_plan.output.clone(),
+                status,
+            },
+            None => finished.next().unwrap(),
+        })
+        .collect()
+}
+
+/// Checks and verifies the partial output of a track that ffmpeg exported,
+/// and moves it into place
+fn finish_track(
+    input_file: &Path,
+    track_plan: &TrackPlan,
+    partial_plan: &TrackPlan,
+    result: Result<(), String>,
+    progress: &TrackProgress,
+    options: &ExportOptions,
+    journal: &Journal,
+) -> TrackReport {
+    let report = |status| TrackReport {
+        typeorder: track_plan.track.typeorder,
+        output: track_plan.output.clone(),
+        status,
+    };
+    let result = result
+        .and_then(|()| check_partial_output(&partial_plan.output))
+        .and_then(|()| match options.verify_tolerance {
+            Some(tolerance) if !is_cancelled() => {
+                verify_output(&partial_plan.output, track_plan, tolerance)
+            }
+            _ => Ok(()),
+        })
+        .and_then(|()| {
+            let lossless = encodes_lossless(&track_plan.track, track_plan.lossless_policy);
+            let stretched = matches!(track_plan.strategy, Strategy::Stretch { .. });
+            match options.verify_content && lossless && !stretched && !is_cancelled() {
+                true => verify_audio_content(
+                    input_file,
+                    &partial_plan.output,
+                    track_plan,
+                    options.temp_dir.as_deref(),
+                ),
+                false => Ok(()),
+            }
+        })
+        .and_then(|()| match options.backup {
+            true => backup_existing(&track_plan.output),
+            false => Ok(()),
+        })
+        .and_then(|()| {
+            rename(&partial_plan.output, &track_plan.output).map_err(|e| {
+                format!(
+                    "Unable to move {} to {}: {}",
+                    partial_plan.output.display(),
+                    track_plan.output.display(),
+                    e
+                )
+            })
+        });
+    progress.update(track_plan.target_duration, None, true);
+
+    if result.is_ok() && !is_cancelled() {
+        journal.record(input_file, track_plan);
+        return report(TrackStatus::Exported);
+    }
+
+    // Whatever ffmpeg left behind is incomplete
+    if partial_plan.output.is_file() && remove_file(&partial_plan.output).is_err() {
+        log::warn!(
+            "Unable to remove incomplete output {}",
+            partial_plan.output.display()
+        );
+    }
+    match result {
+        Err(reason) if !is_cancelled() => report(TrackStatus::Failed(reason)),
+        _ => report(TrackStatus::Cancelled),
+    }
+}
+
+/// A successful ffmpeg run should have left a non-empty file behind
+fn check_partial_output(partial_output: &Path) -> Result<(), String> {
+    match metadata(partial_output) {
+        Ok(metadata) if metadata.len() > 0 => Ok(()),
+        Ok(_) => Err(format!("{} is empty", partial_output.display())),
+        Err(e) => Err(format!(
+            "Unable to read {}: {}",
+            partial_output.display(),
+            e
+        )),
+    }
+}
+
+/// Merges the tracks of a file once all of them are exported. Tracks that
+/// failed are left as separate files.
+fn merge_file(
+    file_plan: &FilePlan,
+    file_report: &FileReport,
+    options: &ExportOptions,
+    journal: &Journal,
+) -> Option<MergeReport> {
+    let merged_output = file_plan.merged_output.as_ref()?;
+    let statuses: Vec<&TrackStatus> = file_report.tracks.iter().map(|t| &t.status).collect();
+    let exported = statuses
+        .iter()
+        .any(|s| matches!(s, TrackStatus::Exported | TrackStatus::AlreadyExported));
+    let status = if is_cancelled() || statuses.contains(&&TrackStatus::Cancelled) {
+        TrackStatus::Cancelled
+    } else if !exported || statuses.iter().any(|s| matches!(s, TrackStatus::Failed(_))) {
+        TrackStatus::Skipped
+    } else {
+        match merge_tracks(file_plan, merged_output, options.backup) {
+            Ok(()) => {
+                journal.record_merged(file_plan, merged_output);
+                TrackStatus::Exported
+            }
+            Err(reason) => TrackStatus::Failed(reason),
         }
-        let duration_difference: f64 =
-            track["Duration"].as_str().unwrap().parse::<f64>().unwrap() - video_duration;
-        let track_language = track["Language"].as_str();
-
-        if (track_filter.language.is_none()
-            || (track_language.is_some()
-                && track_language.unwrap() == track_filter.language.as_ref().unwrap()))
-            && f64::abs(duration_difference) > track_filter.treshold
+    };
+    Some(MergeReport {
+        output: merged_output.clone(),
+        status,
+    })
+}
+
+/// Report of a file whose tracks were exported and merged by an earlier run
+fn merged_report(file_plan: &FilePlan) -> FileReport {
+    FileReport {
+        input: file_plan.input.clone(),
+        tracks: file_plan
+            .tracks
+            .iter()
+            .map(|track_plan| TrackReport {
+                typeorder: track_plan.track.typeorder,
+                output: track_plan.output.clone(),
+                status: match track_plan.action {
+                    TrackAction::Skip => TrackStatus::Skipped,
+                    _ => TrackStatus::AlreadyExported,
+                },
+            })
+            .collect(),
+        merged: file_plan.merged_output.clone().map(|output| MergeReport {
+            output,
+            status: TrackStatus::AlreadyExported,
+        }),
+    }
+}
+
+/// Tracks of a file that are exported by the same job: all tracks that
+/// [`shares_input`] together, and every other track on its own
+fn group_tracks(file_plan: &FilePlan) -> Vec<Vec<&TrackPlan>> {
+    let (shared, separate): (Vec<&TrackPlan>, Vec<&TrackPlan>) =
+        file_plan.tracks.iter().partition(|t| shares_input(t));
+    let mut groups = vec![];
+    if !shared.is_empty() {
+        groups.push(shared);
+    }
+    groups.extend(separate.into_iter().map(|track_plan| vec![track_plan]));
+    groups
+}
+
+/// Exports the tracks of all files, running up to `options.jobs` ffmpeg
+/// processes at once. Tracks of a file that only need to be copied, cut or
+/// padded are exported by one ffmpeg process, so the file is read once.
+/// `on_report` is called per file, in the order of `file_plans`, as soon as
+/// all tracks of the file are exported and merged.
+///
+/// Tracks and merged files completed by an earlier cancelled run are not
+/// exported again. Output conflicts are resolved according to
+/// `options.on_conflict` before the first track is exported; unresolved
+/// conflicts, a lack of free space and a temp directory that can not be
+/// created are returned as error.
+pub fn export_files(
+    file_plans: &[FilePlan],
+    options: &ExportOptions,
+    mut on_report: impl FnMut(&FileReport),
+) -> Result<Vec<FileReport>, String> {
+    if let Some(temp_dir) = &options.temp_dir {
+        create_dir_all(temp_dir).map_err(|e| {
+            format!(
+                "Unable to create temp directory {}: {}",
+                temp_dir.display(),
+                e
+            )
+        })?;
+    }
+    let journal = Journal::load(file_plans);
+    // Files merged by an earlier run no longer have the files of their
+    // tracks, and their merged output is no conflict
+    let merged: Vec<bool> = file_plans.iter().map(|f| journal.is_merged(f)).collect();
+    let pending: Vec<FilePlan> = file_plans
+        .iter()
+        .zip(&merged)
+        .filter(|(_, merged)| !**merged)
+        .map(|(file_plan, _)| file_plan.clone())
+        .collect();
+    let pending = resolve_conflicts(&pending, options.on_conflict, |input, track_plan| {
+        journal.is_completed(input, track_plan)
+    })?;
+    if options.space_check {
+        let temp_dir = options.temp_dir.clone().unwrap_or_else(std::env::temp_dir);
+        check_free_space(&pending, &temp_dir, options.jobs, |input, track_plan| {
+            journal.is_completed(input, track_plan)
+        })?;
+    }
+    let mut pending = pending.into_iter();
+    let file_plans: &Vec<FilePlan> = &file_plans
+        .iter()
+        .zip(&merged)
+        .map(|(file_plan, merged)| match merged {
+            true => file_plan.clone(),
+            false => pending.next().unwrap(),
+        })
+        .collect();
+
+    let track_jobs: Vec<(usize, &FilePlan, Vec<&TrackPlan>)> = file_plans
+        .iter()
+        .enumerate()
+        .filter(|(file_index, _)| !merged[*file_index])
+        .flat_map(|(file_index, file_plan)| {
+            group_tracks(file_plan)
+                .into_iter()
+                .map(move |track_plans| (file_index, file_plan, track_plans))
+        })
+        .collect();
+
+    let mut reports: Vec<FileReport> = file_plans
+        .iter()
+        .zip(&merged)
+        .map(|(file_plan, merged)| match merged {
+            true => merged_report(file_plan),
+            false => FileReport {
+                input: file_plan.input.clone(),
+                tracks: Vec::with_capacity(file_plan.tracks.len()),
+                merged: None,
+            },
+        })
+        .collect();
+
+    let mut reported = 0;
+    let mut report_finished = |reports: &mut [FileReport]| {
+        while reported < file_plans.len()
+            && reports[reported].tracks.len() == file_plans[reported].tracks.len()
         {
-            match duration_difference > 0.0 {
-                true => trim_silence(mkv_file, track, video_duration, output_dir),
-                false => append_silence(mkv_file, track, f64::abs(duration_difference), output_dir),
+            if reports[reported].merged.is_none() {
+                reports[reported].merged =
+                    merge_file(&file_plans[reported], &reports[reported], options, &journal);
             }
-        } else if track_filter.process_all {
-            export_unchanged(mkv_file, track, output_dir);
+            on_report(&reports[reported]);
+            reported += 1;
         }
+    };
+    report_finished(&mut reports);
+
+    run_ordered(
+        options.jobs,
+        track_jobs,
+        |(file_index, file_plan, track_plans)| {
+            let track_reports = export_tracks(&file_plan.input, &track_plans, options, &journal);
+            (file_index, track_reports)
+        },
+        |(file_index, track_reports)| {
+            let track_plans = &file_plans[file_index].tracks;
+            let tracks = &mut reports[file_index].tracks;
+            tracks.extend(track_reports);
+            tracks.sort_by_key(|report| {
+                track_plans
+                    .iter()
+                    .position(|t| t.track.typeorder == report.typeorder)
+            });
+            report_finished(&mut reports);
+        },
+    );
+    if !is_cancelled() {
+        journal.remove();
     }
+
+    Ok(reports)
+}
+
+pub fn export(file_plan: &FilePlan) -> FileReport {
+    export_files(
+        std::slice::from_ref(file_plan),
+        &ExportOptions::default(),
+        |report| println!("{}", report),
+    )
+    .unwrap_or_else(|e| panic!("{}", e))
+    .pop()
+    .unwrap()
 }
@@ -1,239 +1,239 @@
-use std::{ffi::OsString, fs::File, io::Write, path::PathBuf, process::Command, vec};
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    vec,
+};
 
-use serde_json::Value;
-use tempfile::{tempdir, TempDir};
+use tempfile::TempDir;
 
+use super::create_temp_dir;
 use super::output::{
-    create_track_filename, create_track_filepath, create_track_filestem, get_codec_args,
-    get_map_args,
+    create_track_filestem, ffmpeg_channel_layout, get_codec_args, get_delay_args, get_filter_args,
+    get_format_args, get_map_args, get_metadata_args, Container,
+};
+use crate::{
+    codec::{get_audio_ext, output_codec, LosslessPolicy},
+    ffmpeg::{ffmpeg_command, run_ffmpeg},
+    mediainfo::AudioTrack,
+    plan::{Strategy, TrackPlan},
+    progress::TrackProgress,
 };
-use crate::mediainfo::get_audio_ext;
-
-/// Returns: (sample rate, channel layout)
-fn get_track_samplerate_channellayout(input_file: &PathBuf, track: &Value) -> (i64, String) {
-    let mut cmd = Command::new("ffprobe");
-    cmd.args(vec!["-v", "error", "-select_streams"])
-        .arg(
-            "a:".to_owned()
-                + &(track["@typeorder"]
-                    .as_str()
-                    .unwrap()
-                    .parse::<i64>()
-                    .unwrap()
-                    - 1)
-                .to_string(),
-        )
-        .args(vec![
-            "-show_entries",
-            "stream=sample_rate,channel_layout",
-            "-of",
-            "csv=p=0",
-        ])
-        .arg(input_file);
-
-    log::info!("Executing: {:?}", format!("{:?}", cmd).replace('\"', ""));
-    match cmd.output() {
-        Ok(output) => {
-            if !output.status.success() {
-                log::warn!(
-                    "Failed to detect sample rate and channel layout of track {} of file {}\nDefaulting to 48Khz stereo; \u{001b}[31mif this is incorrect, appending silence will fail or the resulting audio file will be malformed!\u{001b}[00m",
-                    get_map_args(track)[1],
-                    input_file.display()
-                );
-                log::trace!(
-                    "FFPROBE error log: {}",
-                    String::from_utf8(output.stderr).unwrap()
-                );
-                (48000, "stereo".to_owned())
-            } else {
-                let output_string: String =
-                    String::from_utf8(output.stdout).unwrap().trim().to_owned();
-                let output_values: Vec<&str> = output_string.split(',').collect();
-                (
-                    output_values[0].parse().unwrap(),
-                    output_values[1].to_owned(),
-                )
-            }
-        }
-        Err(e) => {
-            log::debug!("{}", e);
-            panic!("Error retrieving track channel layout, is FFPROBE installed to path?");
-        }
-    }
-}
 
+/// Encodes silence with the codec of the track. For Matroska tracks, the
+/// silence is written to a Matroska file with the track number of the track,
+/// see [`concat_script`].
 fn generate_silence(
-    input_file: &PathBuf,
-    track: &Value,
+    input_file: &Path,
+    track: &AudioTrack,
+    policy: LosslessPolicy,
     silence_duration: f64,
     tmp_dir: &TempDir,
-) -> PathBuf {
+) -> Result<PathBuf, String> {
     let mut silence_file = tmp_dir
         .path()
         .join(create_track_filestem(input_file, track))
         .as_os_str()
         .to_os_string();
     silence_file.push(".silence");
-    silence_file.push(get_audio_ext(track));
-
-    let (sample_rate, channel_layout) = get_track_samplerate_channellayout(input_file, track);
+    // Elementary input files have no track numbers to select by
+    let container = match track.id {
+        0 => Container::Elementary,
+        _ => Container::Matroska,
+    };
+    match container {
+        Container::Matroska => silence_file.push(".mka"),
+        _ => silence_file.push(get_audio_ext(track, policy)),
+    }
 
-    let mut cmd = Command::new("ffmpeg");
+    let (Some(sampling_rate), Some(channel_layout)) =
+        (track.sampling_rate, ffmpeg_channel_layout(track))
+    else {
+        return Err(format!(
+            "Unknown sample rate or channel count of track {} of file {}, unable to generate silence",
+            get_map_args(track)[1],
+            input_file.display()
+        ));
+    };
+
+    let mut cmd = ffmpeg_command();
     cmd.args(vec!["-f", "lavfi", "-i"])
         .arg(format!(
             "anullsrc=sample_rate={}:channel_layout={}",
-            sample_rate, channel_layout
+            sampling_rate, channel_layout
         ))
         .arg("-t")
-        .arg(silence_duration.to_string())
+        .arg(silence_duration.to_string());
+    cmd.args(vec!["-map", "0:a"]);
+    // The concat demuxer selects the stream of every file by one stream ID,
+    // which is the track number for Matroska files. The DASH mode of the
+    // Matroska muxer is the only way to set the track number of a stream.
+    if container == Container::Matroska {
+        cmd.args(vec!["-dash", "1", "-dash_track_number"])
+            .arg(track.id.to_string());
+    }
+    // Some encoders, like the DTS and TrueHD ones, are marked experimental
+    if let Some(codec) = output_codec(track, policy) {
+        cmd.args(vec!["-c:a", codec.encoder, "-strict", "experimental"]);
+    }
+    cmd.args(get_format_args(track, policy, container))
         .arg(&silence_file);
 
     log::info!("Executing: {:?}", format!("{:?}", cmd).replace('\"', ""));
-    match cmd.output() {
+    match run_ffmpeg(&mut cmd, &[]) {
         Ok(output) => {
             if !output.status.success() {
-                log::error!(
-                    "Failed generate silence file for track {} of file {}",
-                    get_map_args(track)[1],
-                    input_file.display()
-                );
                 log::trace!(
                     "FFMPEG error log: {}",
                     String::from_utf8(output.stderr).unwrap()
                 );
+                return Err(format!(
+                    "Failed generate silence file for track {} of file {}",
+                    get_map_args(track)[1],
+                    input_file.display()
+                ));
             }
+            Ok(silence_file.into())
         }
         Err(e) => {
             log::debug!("{}", e);
             panic!("Error generating silence, is FFMPEG installed to path?");
         }
     }
+}
+
+/// Escapes a path for a single-quoted string of a concat script
+fn concat_quote(path: &Path) -> String {
+    format!("'{}'", path.to_str().unwrap().replace('\'', "'\\''"))
+}
 
-    silence_file.into()
+/// ffconcat script that plays `track` of `input_file` from its delay until
+/// its end, followed by `silence_file`. Matroska tracks are selected by
+/// their track number, which the silence file shares. The paths are written
+/// as given, so they should be absolute.
+pub fn concat_script(input_file: &Path, silence_file: &Path, track: &AudioTrack) -> String {
+    let mut script = vec!["ffconcat version 1.0".to_owned()];
+    if track.id > 0 {
+        script.extend(["stream".to_owned(), format!("exact_stream_id {}", track.id)]);
+    }
+    script.push(format!("file {}", concat_quote(input_file)));
+    // The track starts at its delay; the silence starts where the track ends
+    if let Some(delay) = track.delay.filter(|delay| *delay > 0.0) {
+        script.push(format!("inpoint {}", delay));
+    }
+    script.push(format!("duration {}", track.duration));
+    script.push(format!("file {}", concat_quote(silence_file)));
+    script.join("\n")
 }
 
-fn concat_files(files: Vec<&PathBuf>, tmp_dir: &TempDir, output_file: OsString) {
+/// Streams the packets of the track straight from the input file, followed
+/// by the packets of the silence file
+fn concat_silence(
+    input_file: &Path,
+    silence_file: &Path,
+    track_plan: &TrackPlan,
+    tmp_dir: &TempDir,
+    progress: &TrackProgress,
+) -> Result<(), String> {
+    let track = &track_plan.track;
     let concat_file_path = tmp_dir.path().join("concat.txt");
+    let script = concat_script(
+        &input_file.canonicalize().unwrap(),
+        &silence_file.canonicalize().unwrap(),
+        track,
+    );
     let mut concat_file = File::create(&concat_file_path).expect("Unable to create concat file");
+    writeln!(concat_file, "{}", script).expect("Failed to write content to concat file");
 
-    for f in &files {
-        writeln!(
-            concat_file,
-            "file '{}'",
-            f.canonicalize().unwrap().to_str().unwrap()
-        )
-        .expect("Failed to write content to concat file");
-    }
-
-    let mut cmd = Command::new("ffmpeg");
+    let mut cmd = ffmpeg_command();
     cmd.args(vec!["-y", "-f", "concat", "-safe", "0", "-i"])
         .arg(concat_file_path)
-        .args(vec!["-c", "copy"])
-        .arg(output_file);
+        .args(vec!["-map", "0:a:0", "-map_chapters", "-1"])
+        .args(get_codec_args(track, track_plan.lossless_policy))
+        .args(get_metadata_args(track_plan))
+        // The inpoint removed the delay from the timestamps
+        .args(get_delay_args(track_plan))
+        .args(get_format_args(
+            track,
+            track_plan.lossless_policy,
+            track_plan.container,
+        ))
+        .arg(&track_plan.output);
 
     log::info!("Executing: {:?}", format!("{:?}", cmd).replace('\"', ""));
-    match cmd.output() {
+    match run_ffmpeg(&mut cmd, &[progress]) {
         Ok(output) => {
             if !output.status.success() {
-                log::error!("Failed to concat files: {:?}", &files);
                 log::trace!(
                     "FFMPEG error log: {}",
                     String::from_utf8(output.stderr).unwrap()
                 );
-            }
-        }
-        Err(e) => {
-            log::debug!("{}", e);
-            panic!("Error concatenating files, is FFMPEG installed to path?");
-        }
-    }
-}
-
-fn tmp_export_track(input_file: &PathBuf, track: &Value, tmp_dir: &TempDir) -> PathBuf {
-    let output_filepath = tmp_dir
-        .path()
-        .join(create_track_filename(input_file, track));
-
-    let mut cmd = Command::new("ffmpeg");
-    cmd.arg("-y")
-        .arg("-i")
-        .arg(input_file)
-        .args(get_map_args(track))
-        .args(vec!["-map_chapters", "-1"])
-        .args(get_codec_args(track))
-        .arg(&output_filepath);
-
-    log::info!("Executing: {:?}", format!("{:?}", cmd).replace('\"', ""));
-    match cmd.output() {
-        Ok(output) => {
-            if !output.status.success() {
-                log::error!(
-                    "Failed to export track to temporary file: track {} from file {}",
+                return Err(format!(
+                    "Failed to concatenate track {} of file {} with silence",
                     get_map_args(track)[1],
                     input_file.display()
-                );
-                log::trace!(
-                    "FFMPEG error log: {}",
-                    String::from_utf8(output.stderr).unwrap()
-                );
+                ));
             }
+            Ok(())
         }
         Err(e) => {
             log::debug!("{}", e);
-            panic!("Failed to export track to temporary file, is FFMPEG installed to path?");
+            panic!("Error concatenating files, is FFMPEG installed to path?");
         }
     }
-
-    output_filepath
 }
 
 pub fn append_silence(
     input_file: &PathBuf,
-    track: &Value,
-    silence_duration: f64,
-    output_dir: &Option<OsString>,
-) {
-    let tmp_dir = tempdir().expect("Unable to create temporary directory");
-    match track["Compression_Mode"].as_str().unwrap() {
-        "Lossy" => {
-            let concat_file = tmp_export_track(input_file, track, &tmp_dir);
-            let silence_file = generate_silence(input_file, track, silence_duration, &tmp_dir);
-
-            concat_files(
-                vec![&concat_file, &silence_file],
-                &tmp_dir,
-                create_track_filepath(input_file, track, output_dir),
-            );
+    track_plan: &TrackPlan,
+    temp_dir: Option<&Path>,
+    progress: &TrackProgress,
+) -> Result<(), String> {
+    let track = &track_plan.track;
+    let silence_duration = track_plan.target_duration - track.duration;
+    let policy = track_plan.lossless_policy;
+    let tmp_dir = create_temp_dir(temp_dir);
+    match track_plan.strategy {
+        Strategy::Concat => {
+            let silence_file =
+                generate_silence(input_file, track, policy, silence_duration, &tmp_dir)?;
+            concat_silence(input_file, &silence_file, track_plan, &tmp_dir, progress)
         }
         _ => {
             // Could use the concat method here too, but this is
             // faster, more accurate for lossy encoded tracks, and
             // requires less IO usage
-            let mut cmd = Command::new("ffmpeg");
+            let mut cmd = ffmpeg_command();
             cmd.arg("-y")
                 .arg("-i")
                 .arg(input_file)
                 .args(get_map_args(track))
                 .args(vec!["-map_chapters", "-1"])
-                .arg("-af")
-                .arg(format!("apad=pad_dur={}", silence_duration))
-                .args(get_codec_args(track))
-                .arg(create_track_filepath(input_file, track, output_dir));
+                .args(get_filter_args(
+                    track,
+                    policy,
+                    Some(format!("apad=pad_dur={}", silence_duration)),
+                ))
+                .args(get_codec_args(track, policy))
+                .args(get_metadata_args(track_plan))
+                .args(get_format_args(track, policy, track_plan.container))
+                .arg(&track_plan.output);
 
             log::info!("Executing: {:?}", format!("{:?}", cmd).replace('\"', ""));
-            match cmd.output() {
+            match run_ffmpeg(&mut cmd, &[progress]) {
                 Ok(output) => {
                     if !output.status.success() {
-                        log::error!(
-                            "Failed to append silence to track {} of file {}",
-                            get_map_args(track)[1],
-                            input_file.display()
-                        );
                         log::trace!(
                             "FFMPEG error log: {}",
                             String::from_utf8(output.stderr).unwrap()
                         );
+                        return Err(format!(
+                            "Failed to append silence to track {} of file {}",
+                            get_map_args(track)[1],
+                            input_file.display()
+                        ));
                     }
+                    Ok(())
                 }
                 Err(e) => {
                     log::debug!("{}", e);
@@ -0,0 +1,142 @@
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    fs::rename,
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+
+use crate::plan::{FilePlan, Strategy, TrackAction, TrackPlan};
+
+/// What happens when an output file already exists, or when two tracks of one
+/// run would be exported to the same file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum OnConflict {
+    /// Replace existing files. Tracks of one run that share an output file are
+    /// still an error.
+    #[default]
+    Overwrite,
+    /// Do not export tracks whose output file exists or is taken by an
+    /// earlier track
+    Skip,
+    /// Add a number to the filename until it is free
+    Rename,
+    /// Stop before anything is exported
+    Error,
+}
+
+/// `output` with `_2`, `_3`, … appended to its stem, whichever is the first
+/// that `is_free`
+fn free_path(output: &Path, is_free: impl Fn(&Path) -> bool) -> PathBuf {
+    (2..)
+        .map(|n| {
+            let mut filename: OsString = output.file_stem().unwrap_or_default().to_owned();
+            filename.push(format!("_{}", n));
+            if let Some(ext) = output.extension() {
+                filename.push(".");
+                filename.push(ext);
+            }
+            output.with_file_name(filename)
+        })
+        .find(|path| is_free(path))
+        .unwrap()
+}
+
+fn skip(track_plan: &mut TrackPlan) {
+    track_plan.action = TrackAction::Skip;
+    track_plan.strategy = Strategy::None;
+}
+
+/// Checks the outputs of all files against existing files and against each
+/// other before anything is written, and applies `on_conflict`. Outputs for
+/// which `already_exported` holds belong to an earlier, cancelled run of the
+/// same export and are not conflicts.
+pub fn resolve_conflicts(
+    file_plans: &[FilePlan],
+    on_conflict: OnConflict,
+    already_exported: impl Fn(&Path, &TrackPlan) -> bool,
+) -> Result<Vec<FilePlan>, String> {
+    let mut file_plans = file_plans.to_vec();
+    let mut taken: HashSet<PathBuf> = HashSet::new();
+    let mut conflicts = vec![];
+
+    // Returns whether the output may be written, renaming it if needed
+    let mut claim = |output: &mut PathBuf, is_free: &dyn Fn(&Path) -> bool| {
+        let duplicate = taken.contains(output.as_path());
+        let keep = match on_conflict {
+            _ if !duplicate && is_free(output) => true,
+            OnConflict::Overwrite if !duplicate => true,
+            OnConflict::Skip => {
+                log::warn!("{} already exists, skipping it", output.display());
+                false
+            }
+            OnConflict::Rename => {
+                let renamed = free_path(output, |path| !taken.contains(path) && is_free(path));
+                log::info!(
+                    "{} already exists, exporting to {} instead",
+                    output.display(),
+                    renamed.display()
+                );
+                *output = renamed;
+                true
+            }
+            OnConflict::Overwrite | OnConflict::Error => {
+                conflicts.push(match duplicate {
+                    true => format!("{} is the output of several tracks", output.display()),
+                    false => format!("{} already exists", output.display()),
+                });
+                true
+            }
+        };
+        if keep {
+            taken.insert(output.clone());
+        }
+        keep
+    };
+
+    for file_plan in &mut file_plans {
+        if let Some(merged_output) = &mut file_plan.merged_output {
+            if !claim(merged_output, &|path| !path.exists()) {
+                file_plan.merged_output = None;
+                file_plan.tracks.iter_mut().for_each(skip);
+            }
+        }
+        for track_plan in &mut file_plan.tracks {
+            if track_plan.action == TrackAction::Skip {
+                continue;
+            }
+            let resumable = |path: &Path| {
+                let mut candidate = track_plan.clone();
+                candidate.output = path.to_owned();
+                !path.exists() || already_exported(&file_plan.input, &candidate)
+            };
+            let mut output = track_plan.output.clone();
+            if claim(&mut output, &resumable) {
+                track_plan.output = output;
+            } else {
+                skip(track_plan);
+            }
+        }
+    }
+
+    match conflicts.is_empty() {
+        true => Ok(file_plans),
+        false => Err(format!(
+            "Output conflicts, nothing was exported (see --on-conflict):\n{}",
+            conflicts.join("\n")
+        )),
+    }
+}
+
+/// Moves an existing `output` aside to `<output>.bak`, replacing an older
+/// backup
+pub fn backup_existing(output: &Path) -> Result<(), String> {
+    if !output.is_file() {
+        return Ok(());
+    }
+    let mut backup = output.as_os_str().to_owned();
+    backup.push(".bak");
+    rename(output, &backup)
+        .map_err(|e| format!("Unable to move existing {} aside: {}", output.display(), e))
+}
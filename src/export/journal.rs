@@ -0,0 +1,155 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    fs::{read_to_string, remove_file, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::Serialize;
+
+use crate::plan::{FilePlan, Strategy, TrackAction, TrackPlan};
+
+/// Name of the file in each output directory that lists the completed tracks
+/// of an interrupted export
+const JOURNAL_FILENAME: &str = ".mkvaudur-journal";
+
+#[derive(Serialize)]
+struct JournalEntry<'a> {
+    input: &'a Path,
+    typeorder: i64,
+    strategy: Strategy,
+    target_duration: f64,
+    output: &'a Path,
+}
+
+impl<'a> JournalEntry<'a> {
+    fn new(input: &'a Path, track_plan: &'a TrackPlan) -> JournalEntry<'a> {
+        JournalEntry {
+            input,
+            typeorder: track_plan.track.typeorder,
+            strategy: track_plan.strategy,
+            target_duration: track_plan.target_duration,
+            output: &track_plan.output,
+        }
+    }
+}
+
+fn journal_entry(input: &Path, track_plan: &TrackPlan) -> String {
+    serde_json::to_string(&JournalEntry::new(input, track_plan)).unwrap()
+}
+
+/// Merged output of a file, with the tracks that went into it
+#[derive(Serialize)]
+struct MergeEntry<'a> {
+    input: &'a Path,
+    merged_output: &'a Path,
+    tracks: Vec<JournalEntry<'a>>,
+}
+
+fn merge_entry(file_plan: &FilePlan, merged_output: &Path) -> String {
+    serde_json::to_string(&MergeEntry {
+        input: &file_plan.input,
+        merged_output,
+        tracks: file_plan
+            .tracks
+            .iter()
+            .filter(|t| t.action != TrackAction::Skip)
+            .map(|track_plan| JournalEntry::new(&file_plan.input, track_plan))
+            .collect(),
+    })
+    .unwrap()
+}
+
+fn journal_path(output: &Path) -> PathBuf {
+    output
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(JOURNAL_FILENAME)
+}
+
+/// Records which tracks were exported, so a rerun after a cancelled export
+/// only exports the remaining tracks
+pub struct Journal {
+    journal_files: BTreeSet<PathBuf>,
+    completed: HashSet<String>,
+    lock: Mutex<()>,
+}
+
+impl Journal {
+    /// Reads the journals of all output directories of the plan
+    pub fn load(file_plans: &[FilePlan]) -> Journal {
+        let journal_files: BTreeSet<PathBuf> = file_plans
+            .iter()
+            .flat_map(|file_plan| {
+                file_plan
+                    .tracks
+                    .iter()
+                    .map(|track_plan| track_plan.output.as_path())
+                    .chain(file_plan.merged_output.as_deref())
+                    .map(journal_path)
+            })
+            .collect();
+        let completed = journal_files
+            .iter()
+            .filter_map(|journal_file| read_to_string(journal_file).ok())
+            .flat_map(|content| content.lines().map(str::to_owned).collect::<Vec<String>>())
+            .collect();
+
+        Journal {
+            journal_files,
+            completed,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Whether the track was exported by an earlier, cancelled run
+    pub fn is_completed(&self, input: &Path, track_plan: &TrackPlan) -> bool {
+        track_plan.output.is_file() && self.completed.contains(&journal_entry(input, track_plan))
+    }
+
+    /// Whether the tracks of the file were exported and merged by an earlier,
+    /// cancelled run, which removed the files of the separate tracks
+    pub fn is_merged(&self, file_plan: &FilePlan) -> bool {
+        file_plan
+            .merged_output
+            .as_ref()
+            .is_some_and(|merged_output| {
+                merged_output.is_file()
+                    && self
+                        .completed
+                        .contains(&merge_entry(file_plan, merged_output))
+            })
+    }
+
+    pub fn record(&self, input: &Path, track_plan: &TrackPlan) {
+        self.append(&track_plan.output, journal_entry(input, track_plan));
+    }
+
+    pub fn record_merged(&self, file_plan: &FilePlan, merged_output: &Path) {
+        self.append(merged_output, merge_entry(file_plan, merged_output));
+    }
+
+    fn append(&self, output: &Path, entry: String) {
+        let _lock = self.lock.lock().unwrap();
+        let journal_file = journal_path(output);
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&journal_file)
+            .and_then(|mut file| writeln!(file, "{}", entry));
+        if let Err(e) = written {
+            log::warn!("Unable to write journal {}: {}", journal_file.display(), e);
+        }
+    }
+
+    /// Removes the journals once every track has been processed
+    pub fn remove(&self) {
+        let _lock = self.lock.lock().unwrap();
+        for journal_file in &self.journal_files {
+            if journal_file.is_file() && remove_file(journal_file).is_err() {
+                log::warn!("Unable to remove journal {}", journal_file.display());
+            }
+        }
+    }
+}
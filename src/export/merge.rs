@@ -0,0 +1,82 @@
+use std::{
+    fs::{remove_file, rename},
+    path::Path,
+};
+
+use super::{conflict::backup_existing, output::create_partial_filepath};
+use crate::{
+    ffmpeg::{ffmpeg_command, run_ffmpeg},
+    plan::{FilePlan, TrackAction},
+};
+
+/// Muxes the exported tracks of a file into `merged_output` and removes the
+/// files of the separate tracks. The tracks keep their metadata and delay.
+/// With `backup`, an existing `merged_output` is moved aside first.
+pub fn merge_tracks(
+    file_plan: &FilePlan,
+    merged_output: &Path,
+    backup: bool,
+) -> Result<(), String> {
+    let track_outputs: Vec<&Path> = file_plan
+        .tracks
+        .iter()
+        .filter(|t| t.action != TrackAction::Skip)
+        .map(|t| t.output.as_path())
+        .collect();
+    let partial_output = create_partial_filepath(merged_output);
+
+    let mut cmd = ffmpeg_command();
+    cmd.arg("-y");
+    for track_output in &track_outputs {
+        cmd.arg("-i").arg(track_output);
+    }
+    for index in 0..track_outputs.len() {
+        cmd.arg("-map").arg(format!("{}:a", index));
+    }
+    cmd.args(vec!["-c", "copy", "-f", "matroska"])
+        .arg(&partial_output);
+
+    log::info!("Executing: {:?}", format!("{:?}", cmd).replace('\"', ""));
+    match run_ffmpeg(&mut cmd, &[]) {
+        Ok(output) => {
+            if !output.status.success() {
+                log::trace!(
+                    "FFMPEG error log: {}",
+                    String::from_utf8(output.stderr).unwrap()
+                );
+                if partial_output.is_file() && remove_file(&partial_output).is_err() {
+                    log::warn!(
+                        "Unable to remove incomplete output {}",
+                        partial_output.display()
+                    );
+                }
+                return Err(format!(
+                    "Failed to merge the tracks of file {}",
+                    file_plan.input.display()
+                ));
+            }
+        }
+        Err(e) => {
+            log::debug!("{}", e);
+            panic!("Error merging tracks, is FFMPEG installed to path?");
+        }
+    }
+
+    if backup {
+        backup_existing(merged_output)?;
+    }
+    rename(&partial_output, merged_output).map_err(|e| {
+        format!(
+            "Unable to move {} to {}: {}",
+            partial_output.display(),
+            merged_output.display(),
+            e
+        )
+    })?;
+    for track_output in track_outputs {
+        if remove_file(track_output).is_err() {
+            log::warn!("Unable to remove merged track {}", track_output.display());
+        }
+    }
+    Ok(())
+}
@@ -1,85 +1,427 @@
 use std::{
     ffi::OsString,
-    fs::create_dir_all,
     path::{Path, PathBuf},
 };
 
-use serde_json::Value;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 
-use crate::mediainfo::get_audio_ext;
+use super::template::NameTemplate;
+use crate::{
+    codec::{
+        core_filter, encodes_lossless, get_audio_ext, lossless_encoder_args, output_codec,
+        LosslessPolicy,
+    },
+    language::normalize_language,
+    mediainfo::AudioTrack,
+    plan::{Strategy, TrackPlan},
+};
+
+/// RIFF sizes are 32-bit, so WAV files can not hold more than 4 GiB
+pub const WAV_SIZE_LIMIT: u64 = u32::MAX as u64;
+
+/// File format of an exported track
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Container {
+    /// The elementary file of the codec, e.g. `.ac3` or `.wav`
+    #[default]
+    Elementary,
+    /// Sony Wave64, a WAV variant with 64-bit sizes
+    Wave64,
+    /// Matroska audio
+    Matroska,
+}
+
+/// How the exported tracks are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum OutputFormat {
+    /// One elementary file per track, in the format of the track
+    #[default]
+    Elementary,
+    /// One Matroska audio file per track
+    Mka,
+    /// One Matroska audio file per input file, holding all of its tracks
+    MkaMulti,
+    /// Transcode lossless tracks to FLAC, lossy tracks stay elementary
+    Flac,
+    /// Decode lossless tracks to WAV, lossy tracks stay elementary
+    Wav,
+}
+
+/// Where PCM tracks go that are too large for a WAV file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum LargePcm {
+    /// WAV files that turn into RF64 files when they exceed 4 GiB
+    #[default]
+    Rf64,
+    /// Wave64 files
+    W64,
+    /// Matroska audio files
+    Mka,
+}
+
+impl LargePcm {
+    pub fn container(self) -> Container {
+        match self {
+            LargePcm::Rf64 => Container::Elementary,
+            LargePcm::W64 => Container::Wave64,
+            LargePcm::Mka => Container::Matroska,
+        }
+    }
+}
+
+/// Size in bytes of `duration` seconds of a PCM track, if MediaInfo reported
+/// enough to calculate it
+pub fn estimate_pcm_size(track: &AudioTrack, duration: f64) -> Option<u64> {
+    let bytes_per_second = track.sampling_rate? * track.channels? * ((track.bit_depth? + 7) / 8);
+    Some((duration * bytes_per_second as f64) as u64)
+}
+
+/// Container of an exported track: PCM output that does not fit in a WAV
+/// file goes to the `large_pcm` container
+pub fn choose_container(
+    track: &AudioTrack,
+    policy: LosslessPolicy,
+    duration: f64,
+    large_pcm: LargePcm,
+) -> Container {
+    let pcm = encodes_lossless(track, policy)
+        && output_codec(track, policy).is_some_and(|codec| codec.formats == ["PCM"]);
+    match pcm.then(|| estimate_pcm_size(track, duration)).flatten() {
+        Some(size) if size > WAV_SIZE_LIMIT => {
+            log::info!(
+                "Track {} is about {} MiB as PCM, exporting it as {:?}",
+                track.typeorder,
+                size / (1024 * 1024),
+                large_pcm
+            );
+            large_pcm.container()
+        }
+        _ => Container::Elementary,
+    }
+}
+
+fn output_directory(input_file: &Path, custom_directory: &Option<OsString>) -> PathBuf {
+    match custom_directory.as_ref() {
+        Some(custom_dir) => PathBuf::from(custom_dir),
+        None => input_file.parent().unwrap().to_owned(),
+    }
+}
 
-/// Includes directory, filename, and extension
+/// Includes directory, filename, and extension. The filename, which may
+/// include subdirectories, follows `name_template`.
 pub fn create_track_filepath(
     input_file: &Path,
-    track: &Value,
+    track: &AudioTrack,
+    policy: LosslessPolicy,
+    container: Container,
+    name_template: &NameTemplate,
     custom_directory: &Option<OsString>,
 ) -> OsString {
-    match custom_directory.as_ref() {
-        Some(custom_dir) => {
-            match create_dir_all(custom_dir) {
-                Ok(_) => {}
-                Err(_) => panic!("Unable to create custom output directory"),
-            }
-            PathBuf::from(custom_dir)
-                .join(create_track_filename(input_file, track))
-                .as_os_str()
-                .to_owned()
-        }
-        None => input_file
-            .parent()
-            .unwrap()
-            .join(create_track_filename(input_file, track))
-            .as_os_str()
-            .to_owned(),
-    }
+    output_directory(input_file, custom_directory)
+        .join(name_template.render(
+            input_file,
+            track,
+            &track_extension(track, policy, container),
+        ))
+        .into_os_string()
 }
 
-/// Includes filename and extension. Excludes directory.
-pub fn create_track_filename(input_file: &Path, track: &Value) -> OsString {
-    let mut output_filename = create_track_filestem(input_file, track);
-    output_filename.push(get_audio_ext(track));
-    output_filename
+/// Matroska file that holds all exported tracks of `input_file`
+pub fn create_merged_filepath(input_file: &Path, custom_directory: &Option<OsString>) -> PathBuf {
+    let mut merged_filename = input_file.file_stem().unwrap().to_owned();
+    merged_filename.push("_Audio.mka");
+    output_directory(input_file, custom_directory).join(merged_filename)
+}
+
+fn track_extension(track: &AudioTrack, policy: LosslessPolicy, container: Container) -> String {
+    match container {
+        Container::Elementary => get_audio_ext(track, policy),
+        Container::Wave64 => ".w64".to_owned(),
+        Container::Matroska => ".mka".to_owned(),
+    }
 }
 
 /// Includes filename. Excludes directory and extension
-pub fn create_track_filestem(input_file: &Path, track: &Value) -> OsString {
+pub fn create_track_filestem(input_file: &Path, track: &AudioTrack) -> OsString {
     let mut output_filename = input_file.file_stem().unwrap().to_owned();
     output_filename.push("_Audio");
-    output_filename.push(format!(
-        "{:02}",
-        track["@typeorder"]
-            .as_str()
-            .unwrap()
-            .parse::<f64>()
-            .unwrap(),
-    ));
+    output_filename.push(format!("{:02}", track.typeorder));
     output_filename.push(".");
-    match track["Language"].as_str() {
-        Some(lang) => output_filename.push(lang.to_uppercase()),
-        None => output_filename.push("UND"),
-    }
+    output_filename.push(normalize_language(track.language.as_deref()).to_uppercase());
     output_filename
 }
 
-pub fn get_map_args(track: &Value) -> Vec<String> {
-    vec![
-        "-map".to_owned(),
-        format!(
-            "0:a:{}",
-            track["@typeorder"]
-                .as_str()
-                .unwrap()
-                .parse::<i64>()
-                .unwrap()
-                - 1
-        ),
-    ]
+/// Hidden file in the same directory as `output_file`, with the same
+/// extension so ffmpeg picks the same muxer
+pub fn create_partial_filepath(output_file: &Path) -> PathBuf {
+    let mut partial_filename = OsString::from(".");
+    partial_filename.push(output_file.file_stem().unwrap_or_default());
+    partial_filename.push(".partial");
+    if let Some(ext) = output_file.extension() {
+        partial_filename.push(".");
+        partial_filename.push(ext);
+    }
+    output_file.with_file_name(partial_filename)
 }
 
-pub fn get_codec_args(track: &Value) -> Vec<String> {
-    match track["Compression_Mode"].as_str().unwrap() {
-        "Lossy" => vec!["-c:a".to_string(), "copy".to_string()],
-        "Lossless" => vec![],
-        _ => panic!("Track contains invalid Compression Mode"),
+pub fn get_map_args(track: &AudioTrack) -> Vec<String> {
+    vec!["-map".to_owned(), format!("0:a:{}", track.typeorder - 1)]
+}
+
+/// Lossy tracks and extracted cores are stream copied, lossless tracks are
+/// re-encoded according to `policy`
+pub fn get_codec_args(track: &AudioTrack, policy: LosslessPolicy) -> Vec<String> {
+    match (encodes_lossless(track, policy), core_filter(track, policy)) {
+        (true, _) => lossless_encoder_args(track, policy),
+        (false, Some(filter)) => vec![
+            "-c:a".to_string(),
+            "copy".to_string(),
+            "-bsf:a".to_string(),
+            filter.to_string(),
+        ],
+        (false, None) => vec!["-c:a".to_string(), "copy".to_string()],
+    }
+}
+
+/// ffmpeg muxer of the output, unknown for elementary streams of codecs
+/// mkvaudur does not know
+fn get_muxer(
+    track: &AudioTrack,
+    policy: LosslessPolicy,
+    container: Container,
+) -> Option<&'static str> {
+    match container {
+        Container::Elementary => output_codec(track, policy).map(|codec| codec.muxer),
+        Container::Wave64 => Some("w64"),
+        Container::Matroska => Some("matroska"),
+    }
+}
+
+/// Selects the muxer explicitly, as the extension of partial and temporary
+/// files does not always identify it. WAV files switch to RF64 when they
+/// grow beyond 4 GiB.
+pub fn get_format_args(
+    track: &AudioTrack,
+    policy: LosslessPolicy,
+    container: Container,
+) -> Vec<String> {
+    let Some(muxer) = get_muxer(track, policy, container) else {
+        return vec![];
+    };
+    let mut args = vec!["-f".to_owned(), muxer.to_owned()];
+    if muxer == "wav" {
+        args.extend(["-rf64".to_owned(), "auto".to_owned()]);
+    }
+    args
+}
+
+/// Tag that records what mkvaudur changed, see `TrackPlan::change_tag`
+pub const CHANGE_TAG: &str = "MKVAUDUR";
+
+/// Metadata option that reaches the tags a muxer writes for a single audio
+/// track, and whether it keeps track dispositions. Raw bitstreams and Wave64
+/// have no tags.
+fn tag_support(muxer: &str) -> Option<(&'static str, bool)> {
+    match muxer {
+        "matroska" | "ipod" => Some(("-metadata:s:a:0", true)),
+        "ogg" | "opus" => Some(("-metadata:s:a:0", false)),
+        "flac" | "mp3" | "wav" => Some(("-metadata", false)),
+        _ => None,
+    }
+}
+
+/// Carries the language, title, encoder and flags of the track over to the
+/// output, as far as its container can hold them. Tags of the input file,
+/// including track statistics that no longer apply, are dropped.
+pub fn get_metadata_args(track_plan: &TrackPlan) -> Vec<String> {
+    let track = &track_plan.track;
+    let mut args = vec!["-map_metadata".to_owned(), "-1".to_owned()];
+    let Some((metadata, disposition)) =
+        get_muxer(track, track_plan.lossless_policy, track_plan.container).and_then(tag_support)
+    else {
+        return args;
+    };
+
+    // ffmpeg tags re-encoded tracks with its own encoder
+    let stream_copy = !encodes_lossless(track, track_plan.lossless_policy)
+        && matches!(
+            track_plan.strategy,
+            Strategy::Copy | Strategy::Cut | Strategy::Concat
+        );
+    let tags = [
+        ("language", track.language.as_ref()),
+        ("title", track.title.as_ref()),
+        ("encoder", track.encoder.as_ref().filter(|_| stream_copy)),
+        (CHANGE_TAG, track_plan.change_tag.as_ref()),
+    ];
+    for (key, value) in tags {
+        if let Some(value) = value {
+            args.extend([metadata.to_owned(), format!("{}={}", key, value)]);
+        }
+    }
+
+    if disposition {
+        let flags: Vec<&str> = [
+            (track.default, "default"),
+            (track.forced, "forced"),
+            (track.commentary, "comment"),
+            (track.hearing_impaired, "hearing_impaired"),
+            (track.visual_impaired, "visual_impaired"),
+            (track.original, "original"),
+        ]
+        .into_iter()
+        .filter_map(|(set, flag)| set.then_some(flag))
+        .collect();
+        args.extend([
+            "-disposition:a:0".to_owned(),
+            match flags.is_empty() {
+                true => "0".to_owned(),
+                false => flags.join("+"),
+            },
+        ]);
+    }
+    args
+}
+
+/// Restores the delay of a track whose packets were read from its start, so
+/// their timestamps begin at zero. Matroska output keeps the timestamps of
+/// the input otherwise, and elementary streams can not hold a delay.
+pub fn get_delay_args(track_plan: &TrackPlan) -> Vec<String> {
+    match (track_plan.container, track_plan.track.delay) {
+        (Container::Matroska, Some(delay)) if delay > 0.0 => {
+            vec!["-output_ts_offset".to_owned(), delay.to_string()]
+        }
+        _ => vec![],
+    }
+}
+
+/// ffmpeg name of a MediaInfo channel position
+fn ffmpeg_channel(channel: &str) -> Option<&'static str> {
+    Some(match channel {
+        "L" => "FL",
+        "R" => "FR",
+        "C" | "M" => "FC",
+        "LFE" => "LFE",
+        "LFE2" => "LFE2",
+        "Ls" => "SL",
+        "Rs" => "SR",
+        "Lb" => "BL",
+        "Rb" => "BR",
+        "Cb" => "BC",
+        "Lc" => "FLC",
+        "Rc" => "FRC",
+        "Lw" => "WL",
+        "Rw" => "WR",
+        "Tfl" => "TFL",
+        "Tfr" => "TFR",
+        "Tfc" => "TFC",
+        "Tc" => "TC",
+        "Tbl" => "TBL",
+        "Tbr" => "TBR",
+        "Tbc" => "TBC",
+        _ => return None,
+    })
+}
+
+/// Channel positions in the order of ffmpeg's channel mask, which is the
+/// order its decoders output them in
+const NATIVE_ORDER: [&str; 21] = [
+    "FL", "FR", "FC", "LFE", "BL", "BR", "FLC", "FRC", "BC", "SL", "SR", "TC", "TFL", "TFC", "TFR",
+    "TBL", "TBC", "TBR", "WL", "WR", "LFE2",
+];
+
+/// ffmpeg names of the channel positions MediaInfo reported, in native
+/// order. MediaInfo lists them in bitstream order, e.g. "C L R Ls Rs LFE" for
+/// DTS and AAC, while decoders output them in native order, so only the set
+/// of positions is taken from MediaInfo. None when the layout is missing, has
+/// positions ffmpeg does not know, or does not match the channel count.
+fn native_channels(track: &AudioTrack) -> Option<Vec<&'static str>> {
+    let mut channels: Vec<&str> = track
+        .channel_layout
+        .as_deref()?
+        .split_whitespace()
+        .map(ffmpeg_channel)
+        .collect::<Option<_>>()?;
+    channels.sort_by_key(|channel| NATIVE_ORDER.iter().position(|c| c == channel));
+    match track.channels == Some(channels.len() as i64) {
+        true => Some(channels),
+        false => None,
+    }
+}
+
+/// ffmpeg channel layout of the track in native order, e.g.
+/// "FL+FR+FC+LFE+SL+SR", which encoders accept and which matches the layout
+/// of the decoded track. Without a usable layout from MediaInfo, the usual
+/// layout for the channel count is assumed.
+pub fn ffmpeg_channel_layout(track: &AudioTrack) -> Option<String> {
+    if let Some(channels) = native_channels(track) {
+        return Some(channels.join("+"));
+    }
+    Some(
+        match track.channels? {
+            1 => "mono",
+            2 => "stereo",
+            3 => "2.1",
+            4 => "quad",
+            5 => "5.0",
+            6 => "5.1",
+            7 => "6.1",
+            8 => "7.1",
+            channels if channels > 0 => return Some(format!("{}c", channels)),
+            _ => return None,
+        }
+        .to_owned(),
+    )
+}
+
+/// Filter that sets the channel layout of PCM output to the channels
+/// MediaInfo reported, so the WAVE channel mask is written even when ffmpeg
+/// does not know the layout of the source. The channels are not reordered.
+fn get_channel_filter(track: &AudioTrack, policy: LosslessPolicy) -> Option<String> {
+    let pcm = encodes_lossless(track, policy)
+        && output_codec(track, policy).is_some_and(|codec| codec.formats == ["PCM"]);
+    match pcm {
+        true => Some(format!(
+            "aformat=channel_layouts={}",
+            native_channels(track)?.join("+")
+        )),
+        false => None,
+    }
+}
+
+/// Audio filter arguments for `filter`, preceded by the channel layout filter
+/// when the output is PCM
+pub fn get_filter_args(
+    track: &AudioTrack,
+    policy: LosslessPolicy,
+    filter: Option<String>,
+) -> Vec<String> {
+    let filters: Vec<String> = get_channel_filter(track, policy)
+        .into_iter()
+        .chain(filter)
+        .collect();
+    match filters.is_empty() {
+        true => vec![],
+        false => vec!["-af".to_owned(), filters.join(",")],
+    }
+}
+
+/// Codec arguments for filtered audio, which can not be stream copied. Lossy
+/// tracks are re-encoded with their own codec at their original bit rate,
+/// rather than with the default encoder of the muxer.
+pub fn get_reencode_args(track: &AudioTrack, policy: LosslessPolicy) -> Vec<String> {
+    if encodes_lossless(track, policy) {
+        return lossless_encoder_args(track, policy);
+    }
+    let mut args = vec![];
+    // Some encoders, like the DTS one, are marked experimental
+    if let Some(codec) = output_codec(track, policy) {
+        args.extend(["-c:a", codec.encoder, "-strict", "experimental"].map(str::to_owned));
+    }
+    if let Some(bit_rate) = track.bit_rate {
+        args.extend(["-b:a".to_owned(), bit_rate.to_string()]);
     }
+    args
 }
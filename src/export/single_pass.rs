@@ -0,0 +1,89 @@
+use std::path::PathBuf;
+
+use super::output::{
+    get_codec_args, get_filter_args, get_format_args, get_map_args, get_metadata_args,
+};
+use crate::{
+    ffmpeg::{ffmpeg_command, run_ffmpeg},
+    plan::{Strategy, TrackAction, TrackPlan},
+    progress::TrackProgress,
+};
+
+/// Whether the track can be exported as one of several outputs of a single
+/// ffmpeg command, which needs no temporary files and one filter at most
+pub fn shares_input(track_plan: &TrackPlan) -> bool {
+    track_plan.action != TrackAction::Skip
+        && matches!(
+            track_plan.strategy,
+            Strategy::Copy | Strategy::Cut | Strategy::Apad
+        )
+}
+
+/// Output arguments that carry out the strategy of the track, followed by
+/// its output file
+pub fn output_args(track_plan: &TrackPlan) -> Vec<String> {
+    let track = &track_plan.track;
+    let policy = track_plan.lossless_policy;
+    let mut args = get_map_args(track);
+    args.extend(["-map_chapters".to_owned(), "-1".to_owned()]);
+    match track_plan.strategy {
+        Strategy::Cut => {
+            args.extend(["-t".to_owned(), track_plan.target_duration.to_string()]);
+            args.extend(get_filter_args(track, policy, None));
+        }
+        Strategy::Apad => args.extend(get_filter_args(
+            track,
+            policy,
+            Some(format!(
+                "apad=pad_dur={}",
+                track_plan.target_duration - track.duration
+            )),
+        )),
+        _ => args.extend(get_filter_args(track, policy, None)),
+    }
+    args.extend(get_codec_args(track, policy));
+    args.extend(get_metadata_args(track_plan));
+    args.extend(get_format_args(track, policy, track_plan.container));
+    args.push(track_plan.output.to_string_lossy().into_owned());
+    args
+}
+
+/// Exports several tracks of a file with one ffmpeg command, so the input is
+/// read and demuxed only once. All tracks must satisfy [`shares_input`].
+pub fn export_single_pass(
+    input_file: &PathBuf,
+    track_plans: &[TrackPlan],
+    progress: &[&TrackProgress],
+) -> Result<(), String> {
+    let mut cmd = ffmpeg_command();
+    cmd.arg("-y").arg("-i").arg(input_file);
+    for track_plan in track_plans {
+        cmd.args(output_args(track_plan));
+    }
+
+    log::info!("Executing: {:?}", format!("{:?}", cmd).replace('\"', ""));
+    match run_ffmpeg(&mut cmd, progress) {
+        Ok(output) => {
+            if !output.status.success() {
+                log::trace!(
+                    "FFMPEG error log: {}",
+                    String::from_utf8(output.stderr).unwrap()
+                );
+                return Err(format!(
+                    "Failed to export tracks {} of file {} in a single pass",
+                    track_plans
+                        .iter()
+                        .map(|t| get_map_args(&t.track)[1].clone())
+                        .collect::<Vec<String>>()
+                        .join(", "),
+                    input_file.display()
+                ));
+            }
+            Ok(())
+        }
+        Err(e) => {
+            log::debug!("{}", e);
+            panic!("Error exporting tracks, is FFMPEG installed to path?");
+        }
+    }
+}
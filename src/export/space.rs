@@ -0,0 +1,165 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use super::output::estimate_pcm_size;
+use crate::{
+    codec::{encodes_lossless, output_codec},
+    plan::{FilePlan, Strategy, TrackAction, TrackPlan},
+};
+
+const MIB: u64 = 1024 * 1024;
+
+/// Bytes per second of the track in its source file
+fn source_byte_rate(track_plan: &TrackPlan) -> Option<f64> {
+    let track = &track_plan.track;
+    match (track.stream_size, track.bit_rate) {
+        (Some(stream_size), _) if track.duration > 0.0 => Some(stream_size as f64 / track.duration),
+        (_, Some(bit_rate)) => Some(bit_rate as f64 / 8.0),
+        _ => None,
+    }
+}
+
+/// Size in bytes of the exported track. PCM output is calculated, everything
+/// else is assumed to take as much space per second as the source track,
+/// which overestimates lossless tracks that are compressed on export.
+pub fn estimate_output_size(track_plan: &TrackPlan) -> Option<u64> {
+    if track_plan.action == TrackAction::Skip {
+        return Some(0);
+    }
+    let track = &track_plan.track;
+    let pcm = encodes_lossless(track, track_plan.lossless_policy)
+        && output_codec(track, track_plan.lossless_policy)
+            .is_some_and(|codec| codec.formats == ["PCM"]);
+    match pcm {
+        true => estimate_pcm_size(track, track_plan.target_duration),
+        false => source_byte_rate(track_plan)
+            .map(|byte_rate| (byte_rate * track_plan.target_duration) as u64),
+    }
+}
+
+/// Size in bytes of the temporary files of the track: the silence that is
+/// concatenated to it
+pub fn estimate_temp_size(track_plan: &TrackPlan) -> Option<u64> {
+    if track_plan.strategy != Strategy::Concat {
+        return Some(0);
+    }
+    let silence_duration = track_plan.target_duration - track_plan.track.duration;
+    source_byte_rate(track_plan).map(|byte_rate| (byte_rate * silence_duration) as u64)
+}
+
+/// Closest directory of `path` that exists
+fn existing_directory(path: &Path) -> PathBuf {
+    path.ancestors()
+        .map(|ancestor| match ancestor.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => ancestor,
+        })
+        .find(|ancestor| ancestor.is_dir())
+        .unwrap_or(Path::new("."))
+        .to_owned()
+}
+
+/// Device of the filesystem of `directory`, so directories on the same
+/// filesystem share their free space. Where the platform does not tell,
+/// every directory counts as a filesystem of its own.
+fn filesystem(directory: &Path) -> Result<u64, PathBuf> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if let Ok(metadata) = std::fs::metadata(directory) {
+            return Ok(metadata.dev());
+        }
+    }
+    Err(directory.to_owned())
+}
+
+/// Space a run needs, per filesystem
+#[derive(Default)]
+struct Requirements {
+    /// A directory on the filesystem, for messages, and the bytes needed
+    filesystems: HashMap<Result<u64, PathBuf>, (PathBuf, u64)>,
+}
+
+impl Requirements {
+    fn add(&mut self, path: &Path, bytes: u64) {
+        let directory = existing_directory(path);
+        self.filesystems
+            .entry(filesystem(&directory))
+            .or_insert((directory, 0))
+            .1 += bytes;
+    }
+}
+
+/// Checks before anything is exported that the output and temporary
+/// directories have room for the tracks that still need to be exported.
+/// Tracks whose size can not be estimated are left out.
+pub fn check_free_space(
+    file_plans: &[FilePlan],
+    temp_dir: &Path,
+    jobs: usize,
+    already_exported: impl Fn(&Path, &TrackPlan) -> bool,
+) -> Result<(), String> {
+    let mut requirements = Requirements::default();
+    let mut temp_sizes = vec![];
+    for file_plan in file_plans {
+        let mut merged_size = 0;
+        for track_plan in &file_plan.tracks {
+            if already_exported(&file_plan.input, track_plan) {
+                continue;
+            }
+            let output_size = estimate_output_size(track_plan).unwrap_or(0);
+            requirements.add(&track_plan.output, output_size);
+            merged_size += output_size;
+            temp_sizes.push(estimate_temp_size(track_plan).unwrap_or(0));
+        }
+        if let Some(merged_output) = &file_plan.merged_output {
+            requirements.add(merged_output, merged_size);
+        }
+    }
+    // Temporary files are removed after each track, so only the tracks that
+    // are exported at the same time count
+    temp_sizes.sort_unstable_by(|a, b| b.cmp(a));
+    requirements.add(temp_dir, temp_sizes.iter().take(jobs.max(1)).sum());
+
+    let mut shortages: Vec<String> = requirements
+        .filesystems
+        .into_values()
+        .filter(|(_, needed)| *needed > 0)
+        .filter_map(|(directory, needed)| {
+            let available = fs4::available_space(&directory)
+                .inspect_err(|e| {
+                    log::warn!(
+                        "Unable to determine the free space of {}: {}",
+                        directory.display(),
+                        e
+                    )
+                })
+                .ok()?;
+            log::debug!(
+                "{} needs about {} MiB, {} MiB are available",
+                directory.display(),
+                needed / MIB,
+                available / MIB
+            );
+            (needed > available).then(|| {
+                format!(
+                    "{} needs about {} MiB, but only {} MiB are available",
+                    directory.display(),
+                    needed.div_ceil(MIB),
+                    available / MIB
+                )
+            })
+        })
+        .collect();
+    shortages.sort();
+
+    match shortages.is_empty() {
+        true => Ok(()),
+        false => Err(format!(
+            "Not enough free space, nothing was exported (see --temp-dir and --no-space-check):\n{}",
+            shortages.join("\n")
+        )),
+    }
+}
@@ -0,0 +1,82 @@
+use std::path::PathBuf;
+
+use super::output::{
+    get_filter_args, get_format_args, get_map_args, get_metadata_args, get_reencode_args,
+};
+use crate::{
+    ffmpeg::{ffmpeg_command, run_ffmpeg},
+    plan::TrackPlan,
+    progress::TrackProgress,
+};
+
+/// Changes the speed of a track by `tempo` and cuts or pads the result to
+/// exactly the target duration, which absorbs any small remainder. Without
+/// `preserve_pitch` the track is resampled, which changes its pitch along
+/// with the speed like playing it at another frame rate would.
+pub fn stretch_track(
+    input_file: &PathBuf,
+    track_plan: &TrackPlan,
+    tempo: f64,
+    preserve_pitch: bool,
+    progress: &TrackProgress,
+) -> Result<(), String> {
+    let track = &track_plan.track;
+    let speed_filter = match (preserve_pitch, track.sampling_rate) {
+        (true, _) => format!("atempo={}", tempo),
+        (false, Some(sampling_rate)) => format!(
+            "asetrate={}*{},aresample={}",
+            sampling_rate, tempo, sampling_rate
+        ),
+        (false, None) => {
+            return Err(format!(
+                "Unable to stretch track {} of file {}: unknown sample rate",
+                get_map_args(track)[1],
+                input_file.display()
+            ))
+        }
+    };
+
+    let mut cmd = ffmpeg_command();
+    cmd.arg("-y")
+        .arg("-i")
+        .arg(input_file)
+        .args(get_map_args(track))
+        .args(vec!["-map_chapters", "-1"])
+        .args(get_filter_args(
+            track,
+            track_plan.lossless_policy,
+            Some(format!("{},apad", speed_filter)),
+        ))
+        .arg("-t")
+        .arg(track_plan.target_duration.to_string())
+        .args(get_reencode_args(track, track_plan.lossless_policy))
+        .args(get_metadata_args(track_plan))
+        .args(get_format_args(
+            track,
+            track_plan.lossless_policy,
+            track_plan.container,
+        ))
+        .arg(&track_plan.output);
+
+    log::info!("Executing: {:?}", format!("{:?}", cmd).replace('\"', ""));
+    match run_ffmpeg(&mut cmd, &[progress]) {
+        Ok(output) => {
+            if !output.status.success() {
+                log::trace!(
+                    "FFMPEG error log: {}",
+                    String::from_utf8(output.stderr).unwrap()
+                );
+                return Err(format!(
+                    "Failed to stretch track {} of file {}",
+                    get_map_args(track)[1],
+                    input_file.display()
+                ));
+            }
+            Ok(())
+        }
+        Err(e) => {
+            log::debug!("{}", e);
+            panic!("Error stretching track, is FFMPEG installed to path?");
+        }
+    }
+}
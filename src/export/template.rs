@@ -0,0 +1,241 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{
+    codec::{compression, Compression},
+    language::normalize_language,
+    mediainfo::AudioTrack,
+};
+
+/// Reproduces the names mkvaudur has always used, e.g.
+/// `Movie_Audio02.JPN.ac3`
+pub const DEFAULT_NAME_TEMPLATE: &str = "{stem}_Audio{typeorder:02}.{LANG}{ext}";
+
+/// Characters that are not allowed in filenames on at least one platform
+const UNSAFE_CHARACTERS: [char; 9] = ['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+/// Property of the input file or track that a template can refer to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Placeholder {
+    /// Filename of the input without extension
+    Stem,
+    Id,
+    Typeorder,
+    /// Normalised ISO 639-2/B language, or "und"
+    Lang,
+    /// Uppercase language, or "UND"
+    LangUpper,
+    Title,
+    /// MediaInfo format, e.g. "E-AC-3"
+    Format,
+    /// MediaInfo format profile
+    Profile,
+    /// "lossy", "lossless" or "hybrid"
+    Compression,
+    Channels,
+    /// Channel layout in the usual notation, e.g. "5.1"
+    Layout,
+    SamplingRate,
+    BitDepth,
+    BitRate,
+    /// "default" for default tracks, empty otherwise
+    Default,
+    /// "forced" for forced tracks, empty otherwise
+    Forced,
+    /// Duration in seconds
+    Duration,
+    /// Delay relative to the video in seconds
+    Delay,
+    /// Extension including the leading dot
+    Ext,
+}
+
+const PLACEHOLDERS: [(&str, Placeholder); 19] = [
+    ("stem", Placeholder::Stem),
+    ("id", Placeholder::Id),
+    ("typeorder", Placeholder::Typeorder),
+    ("lang", Placeholder::Lang),
+    ("LANG", Placeholder::LangUpper),
+    ("title", Placeholder::Title),
+    ("format", Placeholder::Format),
+    ("profile", Placeholder::Profile),
+    ("compression", Placeholder::Compression),
+    ("channels", Placeholder::Channels),
+    ("layout", Placeholder::Layout),
+    ("sampling_rate", Placeholder::SamplingRate),
+    ("bit_depth", Placeholder::BitDepth),
+    ("bit_rate", Placeholder::BitRate),
+    ("default", Placeholder::Default),
+    ("forced", Placeholder::Forced),
+    ("duration", Placeholder::Duration),
+    ("delay", Placeholder::Delay),
+    ("ext", Placeholder::Ext),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    /// Placeholder with an optional minimum width, padded with zeros
+    Placeholder(Placeholder, Option<usize>),
+}
+
+/// Output filename with `{placeholder}`s for the properties of a track, see
+/// `PLACEHOLDERS`. A `/` in the template starts a subdirectory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameTemplate {
+    segments: Vec<Segment>,
+}
+
+impl Default for NameTemplate {
+    fn default() -> Self {
+        DEFAULT_NAME_TEMPLATE.parse().unwrap()
+    }
+}
+
+impl FromStr for NameTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut segments = vec![];
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_owned()));
+            }
+            let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+                return Err(format!("unclosed placeholder in {}", s));
+            };
+            let (name, width) = match rest[start + 1..end].split_once(':') {
+                Some((name, width)) => (
+                    name,
+                    Some(
+                        width
+                            .parse()
+                            .map_err(|_| format!("invalid width {} of {{{}}}", width, name))?,
+                    ),
+                ),
+                None => (&rest[start + 1..end], None),
+            };
+            let Some((_, placeholder)) = PLACEHOLDERS.iter().find(|(n, _)| *n == name) else {
+                return Err(format!(
+                    "unknown placeholder {{{}}}, expected one of {}",
+                    name,
+                    PLACEHOLDERS.map(|(n, _)| n).join(", ")
+                ));
+            };
+            segments.push(Segment::Placeholder(*placeholder, width));
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_owned()));
+        }
+        Ok(NameTemplate { segments })
+    }
+}
+
+/// Layout in the usual "5.1" notation, counting LFE channels separately.
+/// Without a channel layout, 6 and 8 channels are assumed to have an LFE.
+fn layout_name(track: &AudioTrack) -> String {
+    let Some(channels) = track.channels else {
+        return String::new();
+    };
+    let lfe = match track.channel_layout.as_deref() {
+        Some(layout) => layout
+            .split_whitespace()
+            .filter(|c| c.starts_with("LFE"))
+            .count() as i64,
+        None => matches!(channels, 6 | 8) as i64,
+    };
+    format!("{}.{}", channels - lfe, lfe)
+}
+
+fn placeholder_value(
+    placeholder: Placeholder,
+    input_file: &Path,
+    track: &AudioTrack,
+    extension: &str,
+) -> String {
+    let optional = |value: Option<i64>| value.map(|v| v.to_string()).unwrap_or_default();
+    match placeholder {
+        Placeholder::Stem => input_file
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+        Placeholder::Id => track.id.to_string(),
+        Placeholder::Typeorder => track.typeorder.to_string(),
+        Placeholder::Lang => normalize_language(track.language.as_deref()),
+        Placeholder::LangUpper => normalize_language(track.language.as_deref()).to_uppercase(),
+        Placeholder::Title => track.title.clone().unwrap_or_default(),
+        Placeholder::Format => track.format.clone(),
+        Placeholder::Profile => track.format_profile.clone().unwrap_or_default(),
+        Placeholder::Compression => match compression(track) {
+            Compression::Lossy => "lossy",
+            Compression::Lossless => "lossless",
+            Compression::Hybrid => "hybrid",
+        }
+        .to_owned(),
+        Placeholder::Channels => optional(track.channels),
+        Placeholder::Layout => layout_name(track),
+        Placeholder::SamplingRate => optional(track.sampling_rate),
+        Placeholder::BitDepth => optional(track.bit_depth),
+        Placeholder::BitRate => optional(track.bit_rate),
+        Placeholder::Default => match track.default {
+            true => "default".to_owned(),
+            false => String::new(),
+        },
+        Placeholder::Forced => match track.forced {
+            true => "forced".to_owned(),
+            false => String::new(),
+        },
+        Placeholder::Duration => track.duration.to_string(),
+        Placeholder::Delay => track.delay.map(|d| d.to_string()).unwrap_or_default(),
+        Placeholder::Ext => extension.to_owned(),
+    }
+}
+
+/// Replaces characters that are unsafe in filenames, including directory
+/// separators, so values can not escape the output directory
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match UNSAFE_CHARACTERS.contains(&c) || c.is_control() {
+            true => '_',
+            false => c,
+        })
+        .collect()
+}
+
+impl NameTemplate {
+    /// Relative path of the exported track. Empty values remove the
+    /// separator in front of them, so `{lang}.{title}` becomes `eng` for
+    /// tracks without a title.
+    pub fn render(&self, input_file: &Path, track: &AudioTrack, extension: &str) -> PathBuf {
+        let mut rendered = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => rendered.push_str(literal),
+                Segment::Placeholder(placeholder, width) => {
+                    let value = sanitize(&placeholder_value(
+                        *placeholder,
+                        input_file,
+                        track,
+                        extension,
+                    ));
+                    if value.is_empty() && rendered.ends_with(['.', '_', '-', ' ']) {
+                        rendered.pop();
+                    }
+                    rendered.push_str(&format!("{:0>width$}", value, width = width.unwrap_or(0)));
+                }
+            }
+        }
+
+        rendered
+            .split('/')
+            .map(|component| component.trim().trim_end_matches('.'))
+            .filter(|component| !component.is_empty())
+            .collect()
+    }
+}
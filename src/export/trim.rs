@@ -1,40 +1,53 @@
-use serde_json::Value;
+use std::path::PathBuf;
 
-use std::{ffi::OsString, path::PathBuf, process::Command};
-
-use super::output::{create_track_filepath, get_codec_args, get_map_args};
+use super::output::{
+    get_codec_args, get_filter_args, get_format_args, get_map_args, get_metadata_args,
+};
+use crate::{
+    ffmpeg::{ffmpeg_command, run_ffmpeg},
+    plan::TrackPlan,
+    progress::TrackProgress,
+};
 
 pub fn trim_silence(
     input_file: &PathBuf,
-    track: &Value,
-    new_track_duration: f64,
-    output_dir: &Option<OsString>,
-) {
-    let mut cmd = Command::new("ffmpeg");
+    track_plan: &TrackPlan,
+    progress: &TrackProgress,
+) -> Result<(), String> {
+    let track = &track_plan.track;
+    let mut cmd = ffmpeg_command();
     cmd.arg("-y")
         .arg("-t")
-        .arg(new_track_duration.to_string())
+        .arg(track_plan.target_duration.to_string())
         .arg("-i")
         .arg(input_file)
         .args(get_map_args(track))
         .args(vec!["-map_chapters", "-1"])
-        .args(get_codec_args(track))
-        .arg(create_track_filepath(input_file, track, output_dir));
+        .args(get_filter_args(track, track_plan.lossless_policy, None))
+        .args(get_codec_args(track, track_plan.lossless_policy))
+        .args(get_metadata_args(track_plan))
+        .args(get_format_args(
+            track,
+            track_plan.lossless_policy,
+            track_plan.container,
+        ))
+        .arg(&track_plan.output);
 
     log::info!("Executing: {:?}", format!("{:?}", cmd).replace('\"', ""));
-    match cmd.output() {
+    match run_ffmpeg(&mut cmd, &[progress]) {
         Ok(output) => {
             if !output.status.success() {
-                log::error!(
-                    "Failed to trim track {} of file {}",
-                    get_map_args(track)[1],
-                    input_file.display()
-                );
                 log::trace!(
                     "FFMPEG error log: {}",
                     String::from_utf8(output.stderr).unwrap()
                 );
+                return Err(format!(
+                    "Failed to trim track {} of file {}",
+                    get_map_args(track)[1],
+                    input_file.display()
+                ));
             }
+            Ok(())
         }
         Err(e) => {
             log::debug!("{}", e);
@@ -0,0 +1,213 @@
+use std::{
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+use super::{create_temp_dir, output::Container};
+use crate::{
+    analysis::measure_peak,
+    codec::{encodes_lossless, output_codec},
+    ffmpeg::{ffmpeg_command, run_ffmpeg},
+    mediainfo::{get_audio_tracks, get_mediainfo, AudioTrack},
+    plan::TrackPlan,
+};
+
+/// Seconds at the end of the shared region that are not hashed, as MediaInfo
+/// reports durations in whole milliseconds
+const CONTENT_HASH_MARGIN: f64 = 0.01;
+
+/// Peak level that volumedetect reports for digital silence
+const DIGITAL_SILENCE_PEAK: f64 = -91.0;
+
+/// Re-probes an exported file and checks that it has the planned duration and
+/// codec, and the same channel count, sample rate, Matroska delay and lossless
+/// bit depth as the source track
+pub fn verify_output(
+    output_file: &PathBuf,
+    track_plan: &TrackPlan,
+    tolerance: f64,
+) -> Result<(), String> {
+    let source = &track_plan.track;
+    let Some(output) = get_audio_tracks(&get_mediainfo(output_file))
+        .into_iter()
+        .next()
+    else {
+        return Err(format!(
+            "Verification failed: {} does not contain an audio track",
+            output_file.display()
+        ));
+    };
+
+    let mut problems = vec![];
+    let duration_difference = output.duration - track_plan.target_duration;
+    if f64::abs(duration_difference) > tolerance {
+        problems.push(format!(
+            "duration is {} instead of {} (difference {})",
+            output.duration, track_plan.target_duration, duration_difference
+        ));
+    }
+    let expected_formats = match output_codec(source, track_plan.lossless_policy) {
+        Some(codec) => codec.formats.to_vec(),
+        None => vec![source.format.as_str()],
+    };
+    if !expected_formats.contains(&output.format.as_str()) {
+        problems.push(format!(
+            "codec changed from {} to {}",
+            expected_formats[0], output.format
+        ));
+    }
+    if source.channels.is_some() && output.channels != source.channels {
+        problems.push(format!(
+            "channel count changed from {:?} to {:?}",
+            source.channels, output.channels
+        ));
+    }
+    if source.sampling_rate.is_some() && output.sampling_rate != source.sampling_rate {
+        problems.push(format!(
+            "sample rate changed from {:?} to {:?}",
+            source.sampling_rate, output.sampling_rate
+        ));
+    }
+
+    // Matroska output keeps the delay of the track, elementary streams lose it
+    if track_plan.container == Container::Matroska {
+        let (source_delay, output_delay) =
+            (source.delay.unwrap_or(0.0), output.delay.unwrap_or(0.0));
+        if f64::abs(output_delay - source_delay) > tolerance {
+            problems.push(format!(
+                "delay is {} instead of {}",
+                output_delay, source_delay
+            ));
+        }
+    }
+    if encodes_lossless(source, track_plan.lossless_policy)
+        && source.bit_depth.is_some()
+        && output.bit_depth != source.bit_depth
+    {
+        problems.push(format!(
+            "bit depth changed from {:?} to {:?}",
+            source.bit_depth, output.bit_depth
+        ));
+    }
+
+    match problems.is_empty() {
+        true => Ok(()),
+        false => Err(format!("Verification failed: {}", problems.join(", "))),
+    }
+}
+
+/// MD5 of the first `end_sample` samples of an audio stream, decoded to 32-bit
+/// PCM so that a reduced bit depth changes the hash
+fn decoded_md5(
+    input_file: &Path,
+    stream: &str,
+    end_sample: i64,
+    hash_file: &Path,
+) -> Result<String, String> {
+    let mut cmd = ffmpeg_command();
+    cmd.arg("-y")
+        .arg("-i")
+        .arg(input_file)
+        .args(vec!["-map", stream])
+        .arg("-af")
+        .arg(format!("atrim=end_sample={}", end_sample))
+        .args(vec!["-c:a", "pcm_s32le", "-f", "md5"])
+        .arg(hash_file);
+
+    log::info!("Executing: {:?}", format!("{:?}", cmd).replace('\"', ""));
+    match run_ffmpeg(&mut cmd, &[]) {
+        Ok(output) => {
+            if !output.status.success() {
+                log::trace!(
+                    "FFMPEG error log: {}",
+                    String::from_utf8(output.stderr).unwrap()
+                );
+                return Err(format!(
+                    "Failed to hash audio stream {} of file {}",
+                    stream,
+                    input_file.display()
+                ));
+            }
+            read_to_string(hash_file)
+                .map(|hash| hash.trim().to_owned())
+                .map_err(|e| format!("Unable to read audio hash: {}", e))
+        }
+        Err(e) => {
+            log::debug!("{}", e);
+            panic!("Error hashing audio, is FFMPEG installed to path?");
+        }
+    }
+}
+
+/// Decodes the source track and the exported file and compares the audio they
+/// have in common, which is everything except the appended silence or the
+/// removed end, and checks that the appended silence is digital silence.
+/// Only meaningful for lossless tracks.
+pub fn verify_audio_content(
+    input_file: &Path,
+    output_file: &Path,
+    track_plan: &TrackPlan,
+    temp_dir: Option<&Path>,
+) -> Result<(), String> {
+    let track = &track_plan.track;
+    let Some(sampling_rate) = track.sampling_rate else {
+        log::warn!(
+            "Unknown sample rate of track {} of file {}, skipping content verification",
+            track.typeorder,
+            input_file.display()
+        );
+        return Ok(());
+    };
+
+    let shared_duration = f64::min(track.duration, track_plan.target_duration);
+    let end_sample =
+        ((shared_duration - CONTENT_HASH_MARGIN).max(0.0) * sampling_rate as f64).floor() as i64;
+
+    let tmp_dir = create_temp_dir(temp_dir);
+    let source_hash = decoded_md5(
+        input_file,
+        &format!("0:a:{}", track.typeorder - 1),
+        end_sample,
+        &tmp_dir.path().join("source.md5"),
+    )?;
+    let output_hash = decoded_md5(
+        output_file,
+        "0:a:0",
+        end_sample,
+        &tmp_dir.path().join("output.md5"),
+    )?;
+
+    if source_hash != output_hash {
+        return Err(format!(
+            "Verification failed: the first {} samples differ from the source track ({} != {})",
+            end_sample, output_hash, source_hash
+        ));
+    }
+
+    if track_plan.target_duration > track.duration + CONTENT_HASH_MARGIN {
+        let output_track = AudioTrack {
+            typeorder: 1,
+            ..track.clone()
+        };
+        match measure_peak(
+            output_file,
+            &output_track,
+            shared_duration + CONTENT_HASH_MARGIN,
+        ) {
+            Some(peak) if peak <= DIGITAL_SILENCE_PEAK => {}
+            Some(peak) => {
+                return Err(format!(
+                    "Verification failed: the padding after {} s is not silent (peak {:.1} dBFS)",
+                    shared_duration, peak
+                ))
+            }
+            None => {
+                return Err(format!(
+                    "Verification failed: unable to measure the padding after {} s",
+                    shared_duration
+                ))
+            }
+        }
+    }
+    Ok(())
+}
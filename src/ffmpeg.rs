@@ -0,0 +1,84 @@
+use std::{
+    io::{self, BufRead, BufReader, Read},
+    process::{Command, Output, Stdio},
+    thread,
+};
+
+use crate::{cancel::is_cancelled, progress::TrackProgress};
+
+/// An ffmpeg command that writes machine readable progress to stdout
+pub fn ffmpeg_command() -> Command {
+    let mut cmd = Command::new("ffmpeg");
+    cmd.args(vec!["-nostats", "-progress", "pipe:1"]);
+
+    // Keep Ctrl-C from reaching ffmpeg directly; on cancellation mkvaudur
+    // kills it instead, so it can not finalize a truncated output file
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x00000200;
+        cmd.creation_flags(CREATE_NEW_PROCESS_GROUP);
+    }
+
+    cmd
+}
+
+/// Runs a command created by [`ffmpeg_command`], forwarding its progress to
+/// the tracks it exports. The returned output contains the stderr of ffmpeg;
+/// stdout is consumed. The process is killed when the export is cancelled,
+/// which results in an unsuccessful exit status.
+pub fn run_ffmpeg(cmd: &mut Command, progress: &[&TrackProgress]) -> io::Result<Output> {
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Drain stderr on a separate thread so ffmpeg never blocks on a full pipe
+    let mut child_stderr = child.stderr.take().unwrap();
+    let stderr_reader = thread::spawn(move || {
+        let mut buffer = Vec::new();
+        let _ = child_stderr.read_to_end(&mut buffer);
+        buffer
+    });
+
+    let mut out_time = 0.0;
+    let mut speed = None;
+    for line in BufReader::new(child.stdout.take().unwrap()).lines() {
+        if is_cancelled() {
+            log::debug!("Killing ffmpeg process {}", child.id());
+            child.kill()?;
+            break;
+        }
+        let line = line?;
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        match key {
+            "out_time_us" | "out_time_ms" => {
+                if let Ok(microseconds) = value.parse::<i64>() {
+                    out_time = microseconds as f64 / 1_000_000.0;
+                }
+            }
+            "speed" => speed = value.trim().trim_end_matches('x').parse::<f64>().ok(),
+            "progress" => {
+                for progress in progress {
+                    progress.update(out_time, speed, false);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let status = child.wait()?;
+    Ok(Output {
+        status,
+        stdout: Vec::new(),
+        stderr: stderr_reader.join().unwrap(),
+    })
+}
@@ -0,0 +1,53 @@
+use std::{
+    collections::BTreeMap,
+    sync::{mpsc, Mutex},
+    thread,
+};
+
+/// Runs `job` for every item on at most `jobs` threads at a time.
+///
+/// `on_done` is called on the calling thread with the results in the order of
+/// `items`, as soon as all preceding results are available. This keeps report
+/// output ordered no matter which job finishes first.
+pub fn run_ordered<T, R>(
+    jobs: usize,
+    items: Vec<T>,
+    job: impl Fn(T) -> R + Sync,
+    mut on_done: impl FnMut(R),
+) where
+    T: Send,
+    R: Send,
+{
+    let worker_count = jobs.clamp(1, items.len().max(1));
+    let queue = Mutex::new(items.into_iter().enumerate());
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..worker_count {
+            let sender = sender.clone();
+            let (queue, job) = (&queue, &job);
+            scope.spawn(move || loop {
+                let next = queue.lock().unwrap().next();
+                match next {
+                    Some((index, item)) => {
+                        if sender.send((index, job(item))).is_err() {
+                            break;
+                        }
+                    }
+                    None => break,
+                }
+            });
+        }
+        drop(sender);
+
+        let mut pending = BTreeMap::new();
+        let mut next_index = 0;
+        for (index, result) in receiver {
+            pending.insert(index, result);
+            while let Some(result) = pending.remove(&next_index) {
+                on_done(result);
+                next_index += 1;
+            }
+        }
+    });
+}
@@ -0,0 +1,261 @@
+use std::str::FromStr;
+
+/// Code for tracks without a (known) language
+pub const UNDETERMINED: &str = "und";
+
+/// English name, ISO 639-1, ISO 639-2/B and ISO 639-2/T code of the languages
+/// with a two-letter code
+const LANGUAGES: [(&str, &str, &str, &str); 183] = [
+    ("Abkhazian", "ab", "abk", "abk"),
+    ("Afar", "aa", "aar", "aar"),
+    ("Afrikaans", "af", "afr", "afr"),
+    ("Akan", "ak", "aka", "aka"),
+    ("Albanian", "sq", "alb", "sqi"),
+    ("Amharic", "am", "amh", "amh"),
+    ("Arabic", "ar", "ara", "ara"),
+    ("Aragonese", "an", "arg", "arg"),
+    ("Armenian", "hy", "arm", "hye"),
+    ("Assamese", "as", "asm", "asm"),
+    ("Avaric", "av", "ava", "ava"),
+    ("Avestan", "ae", "ave", "ave"),
+    ("Aymara", "ay", "aym", "aym"),
+    ("Azerbaijani", "az", "aze", "aze"),
+    ("Bambara", "bm", "bam", "bam"),
+    ("Bashkir", "ba", "bak", "bak"),
+    ("Basque", "eu", "baq", "eus"),
+    ("Belarusian", "be", "bel", "bel"),
+    ("Bengali", "bn", "ben", "ben"),
+    ("Bislama", "bi", "bis", "bis"),
+    ("Bosnian", "bs", "bos", "bos"),
+    ("Breton", "br", "bre", "bre"),
+    ("Bulgarian", "bg", "bul", "bul"),
+    ("Burmese", "my", "bur", "mya"),
+    ("Catalan", "ca", "cat", "cat"),
+    ("Chamorro", "ch", "cha", "cha"),
+    ("Chechen", "ce", "che", "che"),
+    ("Chichewa", "ny", "nya", "nya"),
+    ("Chinese", "zh", "chi", "zho"),
+    ("Church Slavic", "cu", "chu", "chu"),
+    ("Chuvash", "cv", "chv", "chv"),
+    ("Cornish", "kw", "cor", "cor"),
+    ("Corsican", "co", "cos", "cos"),
+    ("Cree", "cr", "cre", "cre"),
+    ("Croatian", "hr", "hrv", "hrv"),
+    ("Czech", "cs", "cze", "ces"),
+    ("Danish", "da", "dan", "dan"),
+    ("Divehi", "dv", "div", "div"),
+    ("Dutch", "nl", "dut", "nld"),
+    ("Dzongkha", "dz", "dzo", "dzo"),
+    ("English", "en", "eng", "eng"),
+    ("Esperanto", "eo", "epo", "epo"),
+    ("Estonian", "et", "est", "est"),
+    ("Ewe", "ee", "ewe", "ewe"),
+    ("Faroese", "fo", "fao", "fao"),
+    ("Fijian", "fj", "fij", "fij"),
+    ("Finnish", "fi", "fin", "fin"),
+    ("French", "fr", "fre", "fra"),
+    ("Western Frisian", "fy", "fry", "fry"),
+    ("Fulah", "ff", "ful", "ful"),
+    ("Gaelic", "gd", "gla", "gla"),
+    ("Galician", "gl", "glg", "glg"),
+    ("Ganda", "lg", "lug", "lug"),
+    ("Georgian", "ka", "geo", "kat"),
+    ("German", "de", "ger", "deu"),
+    ("Greek", "el", "gre", "ell"),
+    ("Kalaallisut", "kl", "kal", "kal"),
+    ("Guarani", "gn", "grn", "grn"),
+    ("Gujarati", "gu", "guj", "guj"),
+    ("Haitian", "ht", "hat", "hat"),
+    ("Hausa", "ha", "hau", "hau"),
+    ("Hebrew", "he", "heb", "heb"),
+    ("Herero", "hz", "her", "her"),
+    ("Hindi", "hi", "hin", "hin"),
+    ("Hiri Motu", "ho", "hmo", "hmo"),
+    ("Hungarian", "hu", "hun", "hun"),
+    ("Icelandic", "is", "ice", "isl"),
+    ("Ido", "io", "ido", "ido"),
+    ("Igbo", "ig", "ibo", "ibo"),
+    ("Indonesian", "id", "ind", "ind"),
+    ("Interlingua", "ia", "ina", "ina"),
+    ("Interlingue", "ie", "ile", "ile"),
+    ("Inuktitut", "iu", "iku", "iku"),
+    ("Inupiaq", "ik", "ipk", "ipk"),
+    ("Irish", "ga", "gle", "gle"),
+    ("Italian", "it", "ita", "ita"),
+    ("Japanese", "ja", "jpn", "jpn"),
+    ("Javanese", "jv", "jav", "jav"),
+    ("Kannada", "kn", "kan", "kan"),
+    ("Kanuri", "kr", "kau", "kau"),
+    ("Kashmiri", "ks", "kas", "kas"),
+    ("Kazakh", "kk", "kaz", "kaz"),
+    ("Central Khmer", "km", "khm", "khm"),
+    ("Kikuyu", "ki", "kik", "kik"),
+    ("Kinyarwanda", "rw", "kin", "kin"),
+    ("Kirghiz", "ky", "kir", "kir"),
+    ("Komi", "kv", "kom", "kom"),
+    ("Kongo", "kg", "kon", "kon"),
+    ("Korean", "ko", "kor", "kor"),
+    ("Kuanyama", "kj", "kua", "kua"),
+    ("Kurdish", "ku", "kur", "kur"),
+    ("Lao", "lo", "lao", "lao"),
+    ("Latin", "la", "lat", "lat"),
+    ("Latvian", "lv", "lav", "lav"),
+    ("Limburgan", "li", "lim", "lim"),
+    ("Lingala", "ln", "lin", "lin"),
+    ("Lithuanian", "lt", "lit", "lit"),
+    ("Luba-Katanga", "lu", "lub", "lub"),
+    ("Luxembourgish", "lb", "ltz", "ltz"),
+    ("Macedonian", "mk", "mac", "mkd"),
+    ("Malagasy", "mg", "mlg", "mlg"),
+    ("Malay", "ms", "may", "msa"),
+    ("Malayalam", "ml", "mal", "mal"),
+    ("Maltese", "mt", "mlt", "mlt"),
+    ("Manx", "gv", "glv", "glv"),
+    ("Maori", "mi", "mao", "mri"),
+    ("Marathi", "mr", "mar", "mar"),
+    ("Marshallese", "mh", "mah", "mah"),
+    ("Mongolian", "mn", "mon", "mon"),
+    ("Nauru", "na", "nau", "nau"),
+    ("Navajo", "nv", "nav", "nav"),
+    ("North Ndebele", "nd", "nde", "nde"),
+    ("South Ndebele", "nr", "nbl", "nbl"),
+    ("Ndonga", "ng", "ndo", "ndo"),
+    ("Nepali", "ne", "nep", "nep"),
+    ("Norwegian", "no", "nor", "nor"),
+    ("Norwegian Bokmål", "nb", "nob", "nob"),
+    ("Norwegian Nynorsk", "nn", "nno", "nno"),
+    ("Occitan", "oc", "oci", "oci"),
+    ("Ojibwa", "oj", "oji", "oji"),
+    ("Oriya", "or", "ori", "ori"),
+    ("Oromo", "om", "orm", "orm"),
+    ("Ossetian", "os", "oss", "oss"),
+    ("Pali", "pi", "pli", "pli"),
+    ("Pashto", "ps", "pus", "pus"),
+    ("Persian", "fa", "per", "fas"),
+    ("Polish", "pl", "pol", "pol"),
+    ("Portuguese", "pt", "por", "por"),
+    ("Punjabi", "pa", "pan", "pan"),
+    ("Quechua", "qu", "que", "que"),
+    ("Romanian", "ro", "rum", "ron"),
+    ("Romansh", "rm", "roh", "roh"),
+    ("Rundi", "rn", "run", "run"),
+    ("Russian", "ru", "rus", "rus"),
+    ("Northern Sami", "se", "sme", "sme"),
+    ("Samoan", "sm", "smo", "smo"),
+    ("Sango", "sg", "sag", "sag"),
+    ("Sanskrit", "sa", "san", "san"),
+    ("Sardinian", "sc", "srd", "srd"),
+    ("Serbian", "sr", "srp", "srp"),
+    ("Shona", "sn", "sna", "sna"),
+    ("Sindhi", "sd", "snd", "snd"),
+    ("Sinhala", "si", "sin", "sin"),
+    ("Slovak", "sk", "slo", "slk"),
+    ("Slovenian", "sl", "slv", "slv"),
+    ("Somali", "so", "som", "som"),
+    ("Southern Sotho", "st", "sot", "sot"),
+    ("Spanish", "es", "spa", "spa"),
+    ("Sundanese", "su", "sun", "sun"),
+    ("Swahili", "sw", "swa", "swa"),
+    ("Swati", "ss", "ssw", "ssw"),
+    ("Swedish", "sv", "swe", "swe"),
+    ("Tagalog", "tl", "tgl", "tgl"),
+    ("Tahitian", "ty", "tah", "tah"),
+    ("Tajik", "tg", "tgk", "tgk"),
+    ("Tamil", "ta", "tam", "tam"),
+    ("Tatar", "tt", "tat", "tat"),
+    ("Telugu", "te", "tel", "tel"),
+    ("Thai", "th", "tha", "tha"),
+    ("Tibetan", "bo", "tib", "bod"),
+    ("Tigrinya", "ti", "tir", "tir"),
+    ("Tonga", "to", "ton", "ton"),
+    ("Tsonga", "ts", "tso", "tso"),
+    ("Tswana", "tn", "tsn", "tsn"),
+    ("Turkish", "tr", "tur", "tur"),
+    ("Turkmen", "tk", "tuk", "tuk"),
+    ("Twi", "tw", "twi", "twi"),
+    ("Uighur", "ug", "uig", "uig"),
+    ("Ukrainian", "uk", "ukr", "ukr"),
+    ("Urdu", "ur", "urd", "urd"),
+    ("Uzbek", "uz", "uzb", "uzb"),
+    ("Venda", "ve", "ven", "ven"),
+    ("Vietnamese", "vi", "vie", "vie"),
+    ("Volapük", "vo", "vol", "vol"),
+    ("Walloon", "wa", "wln", "wln"),
+    ("Welsh", "cy", "wel", "cym"),
+    ("Wolof", "wo", "wol", "wol"),
+    ("Xhosa", "xh", "xho", "xho"),
+    ("Sichuan Yi", "ii", "iii", "iii"),
+    ("Yiddish", "yi", "yid", "yid"),
+    ("Yoruba", "yo", "yor", "yor"),
+    ("Zhuang", "za", "zha", "zha"),
+    ("Zulu", "zu", "zul", "zul"),
+];
+
+/// Normalises an ISO 639-1, ISO 639-2/B or /T code, a BCP 47 tag like
+/// "en-US" or an English language name like MediaInfo reports it to the
+/// ISO 639-2/B code that Matroska uses, e.g. "ger" for "de", "deu",
+/// "de-AT" and "German". Unknown codes are kept in lowercase, missing and
+/// empty ones are "und".
+pub fn normalize_language(language: Option<&str>) -> String {
+    let language = language.map(str::trim).unwrap_or_default();
+    // BCP 47 tags start with the language subtag, regions and scripts follow
+    let code = language
+        .split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    if code.is_empty() {
+        return UNDETERMINED.to_owned();
+    }
+    LANGUAGES
+        .iter()
+        .find(|(name, part1, part2b, part2t)| {
+            [*part1, *part2b, *part2t].contains(&code.as_str())
+                || name.eq_ignore_ascii_case(language)
+        })
+        .map(|(_, _, part2b, _)| part2b.to_string())
+        .unwrap_or(match language.contains(' ') {
+            // Names like "Norwegian Bokmal" that are not in the table
+            true => language.to_lowercase(),
+            false => code,
+        })
+}
+
+/// Languages given to `--language`: a comma-separated list of languages to
+/// select, each of which may instead be excluded with a leading `!`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LanguageFilter {
+    /// Normalised languages to select, any when empty
+    pub include: Vec<String>,
+    /// Normalised languages to leave out
+    pub exclude: Vec<String>,
+}
+
+impl LanguageFilter {
+    /// Whether a track with the MediaInfo `language` passes the filter.
+    /// Tracks without a language only match "und".
+    pub fn matches(&self, language: Option<&str>) -> bool {
+        let language = normalize_language(language);
+        (self.include.is_empty() || self.include.contains(&language))
+            && !self.exclude.contains(&language)
+    }
+}
+
+impl FromStr for LanguageFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = LanguageFilter::default();
+        for language in s.split(',').map(str::trim) {
+            let (list, language) = match language.strip_prefix('!') {
+                Some(excluded) => (&mut filter.exclude, excluded.trim()),
+                None => (&mut filter.include, language),
+            };
+            if language.is_empty() {
+                return Err(format!("empty language in {}", s));
+            }
+            list.push(normalize_language(Some(language)));
+        }
+        Ok(filter)
+    }
+}
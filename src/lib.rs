@@ -1,22 +1,56 @@
 use std::{
-    ffi::OsString,
     fmt,
     fs::read_dir,
     path::{Path, PathBuf},
 };
 
 use args::OperationMode;
+use language::LanguageFilter;
+use mediainfo::AudioTrack;
+use plan::{plan_file, FilePlan, PlanOptions};
+use select::Selection;
 use serde_json::Value;
 
+pub mod analysis;
 pub mod args;
+pub mod cancel;
+pub mod codec;
 pub mod display;
 pub mod export;
+pub mod ffmpeg;
+pub mod jobs;
+pub mod language;
 pub mod mediainfo;
+pub mod plan;
+pub mod progress;
+pub mod select;
+pub mod sync;
 
 pub struct TrackFilter {
     pub treshold: f64,
-    pub language: Option<String>,
+    pub language: Option<LanguageFilter>,
     pub process_all: bool,
+    /// Parsed `--select` expression
+    pub selection: Option<Selection>,
+}
+
+impl TrackFilter {
+    pub fn matches_language(&self, track: &AudioTrack) -> bool {
+        self.language
+            .as_ref()
+            .is_none_or(|language| language.matches(track.language.as_deref()))
+    }
+
+    /// Whether the track passes the language filter and the selection
+    /// expression. `duration_difference` is the track duration minus the
+    /// video duration.
+    pub fn selects(&self, track: &AudioTrack, duration_difference: f64) -> bool {
+        self.matches_language(track)
+            && self
+                .selection
+                .as_ref()
+                .is_none_or(|selection| selection.matches(track, duration_difference))
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -57,36 +91,31 @@ pub fn get_files(filepath: &Path) -> Result<Vec<PathBuf>, NoMkvFound> {
     Ok(input_files)
 }
 
+/// Plans the export of the audio tracks of `mkv_file` and displays or
+/// executes the plan depending on the operation mode
 pub fn process_mkv_file(
-    mkv_file: &PathBuf,
+    mkv_file: &Path,
     mkv_mediainfo: &Value,
     ref_mediainfo: &Value,
     operation_mode: &OperationMode,
     track_filter: &TrackFilter,
-    output_dir: &Option<OsString>,
-) {
-    let video_track: &Value = ref_mediainfo["media"]["track"]
-        .as_array()
-        .unwrap()
-        .iter()
-        .filter(|t| t["@type"] == "Video")
-        .collect::<Vec<&Value>>()[0];
-    let video_track_duration = video_track["Duration"]
-        .as_str()
-        .unwrap()
-        .parse::<f64>()
-        .unwrap();
+    plan_options: &PlanOptions,
+) -> FilePlan {
+    let file_plan = plan_file(
+        mkv_file,
+        mkv_mediainfo,
+        ref_mediainfo,
+        track_filter,
+        plan_options,
+    );
 
     match operation_mode {
-        OperationMode::Display => {
-            display::display(mkv_mediainfo, video_track_duration, track_filter)
+        OperationMode::Display => display::display(&file_plan, plan_options),
+        OperationMode::Export => {
+            export::export(&file_plan);
         }
-        OperationMode::Export => export::export(
-            mkv_file,
-            mkv_mediainfo,
-            video_track_duration,
-            track_filter,
-            output_dir,
-        ),
+        OperationMode::Plan | OperationMode::Apply { .. } | OperationMode::SyncCheck { .. } => {}
     }
+
+    file_plan
 }
@@ -1,56 +1,205 @@
-use std::io::{stdin, stdout, Write};
+use std::fs::read_to_string;
+use std::io::{stderr, stdin, stdout, IsTerminal, Write};
 use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::sync::Arc;
 
-use clap::Parser;
+use clap::{error::ErrorKind, CommandFactory, Parser};
 
-use mkvaudur::args::MkvAudurArgs;
+use mkvaudur::args::{MkvAudurArgs, OperationMode};
+use mkvaudur::cancel::{cancel, is_cancelled};
+use mkvaudur::display::display;
+use mkvaudur::export::{export_files, ExportOptions, FileReport};
+use mkvaudur::jobs::run_ordered;
 use mkvaudur::mediainfo::get_mediainfo;
-use mkvaudur::{get_files, process_mkv_file, TrackFilter};
+use mkvaudur::plan::{plan_file, ExportPlan, FilePlan, PlanOptions};
+use mkvaudur::progress::{ProgressCallback, ProgressEvent, TerminalProgress};
+use mkvaudur::sync::sync_check;
+use mkvaudur::{get_files, TrackFilter};
 
 fn main() {
     let args = MkvAudurArgs::parse();
+    if args.filepath.is_none() && !matches!(args.mode, OperationMode::Apply { .. }) {
+        MkvAudurArgs::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "the input filepath is required except for apply",
+            )
+            .exit();
+    }
 
     env_logger::Builder::new()
         .filter_level(args.verbose.log_level_filter())
         .init();
 
-    let mkv_files = iter_get_files(&args.filepath, "input");
-    let reference_files = args
-        .reference
-        .as_ref()
-        .map(|p| iter_get_files(p.as_path(), "reference"));
+    // Other modes write nothing that could be left incomplete
+    if matches!(
+        args.mode,
+        OperationMode::Export | OperationMode::Apply { .. }
+    ) {
+        ctrlc::set_handler(|| {
+            if is_cancelled() {
+                exit(130);
+            }
+            eprintln!("\nCancelling export, press Ctrl-C again to quit immediately");
+            cancel();
+        })
+        .expect("Unable to set Ctrl-C handler");
+    }
+
+    let terminal_progress = stderr()
+        .is_terminal()
+        .then(|| Arc::new(TerminalProgress::default()));
+    let export_options = ExportOptions {
+        jobs: args.jobs,
+        on_progress: terminal_progress.clone().map(|terminal_progress| {
+            Arc::new(move |event: &ProgressEvent| terminal_progress.update(event))
+                as ProgressCallback
+        }),
+        verify_tolerance: (!args.no_verify).then_some(args.tolerance),
+        verify_content: args.verify_content,
+        on_conflict: args.on_conflict,
+        backup: args.backup,
+        temp_dir: args.temp_dir,
+        space_check: !args.no_space_check,
+    };
+    let print_report = |report: &FileReport| {
+        if let Some(terminal_progress) = &terminal_progress {
+            terminal_progress.clear();
+        }
+        println!("{}", report);
+    };
+
+    if let OperationMode::Apply { plan } = &args.mode {
+        let plan_json = read_to_string(plan).expect("Unable to read export plan");
+        let plan: ExportPlan = serde_json::from_str(&plan_json).expect("Invalid export plan");
+        if let Err(e) = plan.validate() {
+            eprintln!("{}", e);
+            exit(1);
+        }
+        export_or_exit(&plan.files, &export_options, print_report);
+        exit_if_cancelled();
+        return;
+    }
+
+    let mkv_files = iter_get_files(&args.filepath.unwrap(), "input");
+    let file_pairs: Vec<(PathBuf, Option<PathBuf>)> = match args.reference.as_ref() {
+        Some(reference) => mkv_files
+            .into_iter()
+            .zip(
+                iter_get_files(reference.as_path(), "reference")
+                    .into_iter()
+                    .map(Some),
+            )
+            .collect(),
+        None => mkv_files.into_iter().map(|f| (f, None)).collect(),
+    };
 
     let track_filter = TrackFilter {
         treshold: args.treshold,
         language: args.language,
         process_all: args.all,
+        selection: args.select,
     };
 
-    if reference_files.is_some() {
-        for (mkv_file, ref_file) in mkv_files.iter().zip(reference_files.unwrap().iter()) {
-            let mkv_mediainfo = get_mediainfo(mkv_file);
-            let ref_mediainfo = get_mediainfo(ref_file);
-            process_mkv_file(
-                mkv_file,
-                &mkv_mediainfo,
-                &ref_mediainfo,
-                &args.mode,
-                &track_filter,
-                &args.output,
-            );
-        }
-    } else {
-        for mkv_file in mkv_files {
+    if let OperationMode::SyncCheck {
+        against,
+        max_offset,
+    } = args.mode
+    {
+        run_ordered(
+            args.jobs,
+            file_pairs,
+            |(mkv_file, ref_file)| {
+                let mkv_mediainfo = get_mediainfo(&mkv_file);
+                let ref_mediainfo = ref_file.as_ref().map(get_mediainfo);
+                let results = sync_check(
+                    &mkv_file,
+                    &mkv_mediainfo,
+                    ref_file.as_deref().zip(ref_mediainfo.as_ref()),
+                    &track_filter,
+                    against,
+                    max_offset,
+                );
+                (mkv_file, results)
+            },
+            |(mkv_file, results)| {
+                println!("{}", mkv_file.file_name().unwrap().to_str().unwrap());
+                for result in results {
+                    println!("{}", result);
+                }
+                println!();
+            },
+        );
+        return;
+    }
+
+    let plan_options = PlanOptions {
+        output_dir: args.output,
+        tail_check: args.tail_check,
+        silence_threshold: args.silence_threshold,
+        stretch: args.stretch,
+        stretch_against: args.stretch_against,
+        speed_correct: args.speed_correct,
+        preserve_pitch: args.preserve_pitch,
+        lossless_policies: args.lossless_policy,
+        large_pcm: args.large_pcm,
+        output_format: args.output_format,
+        name_template: args.name_template,
+        tag_changes: args.tag_changes,
+    };
+
+    let mut plan = ExportPlan::default();
+    run_ordered(
+        args.jobs,
+        file_pairs,
+        |(mkv_file, ref_file)| {
             let mkv_mediainfo = get_mediainfo(&mkv_file);
-            process_mkv_file(
+            let ref_mediainfo = match ref_file {
+                Some(ref_file) => get_mediainfo(&ref_file),
+                None => mkv_mediainfo.clone(),
+            };
+            plan_file(
                 &mkv_file,
                 &mkv_mediainfo,
-                &mkv_mediainfo,
-                &args.mode,
+                &ref_mediainfo,
                 &track_filter,
-                &args.output,
-            );
+                &plan_options,
+            )
+        },
+        |file_plan| {
+            if let OperationMode::Display = args.mode {
+                display(&file_plan, &plan_options);
+            }
+            plan.files.push(file_plan);
+        },
+    );
+
+    match args.mode {
+        OperationMode::Export => {
+            export_or_exit(&plan.files, &export_options, print_report);
+            exit_if_cancelled();
         }
+        OperationMode::Plan => println!("{}", serde_json::to_string_pretty(&plan).unwrap()),
+        OperationMode::Display | OperationMode::Apply { .. } | OperationMode::SyncCheck { .. } => {}
+    }
+}
+
+fn export_or_exit(
+    file_plans: &[FilePlan],
+    export_options: &ExportOptions,
+    print_report: impl FnMut(&FileReport),
+) {
+    if let Err(e) = export_files(file_plans, export_options, print_report) {
+        eprintln!("{}", e);
+        exit(1);
+    }
+}
+
+fn exit_if_cancelled() {
+    if is_cancelled() {
+        eprintln!("Export cancelled, rerun the same export to continue with the remaining tracks");
+        exit(130);
     }
 }
 
@@ -1,3 +1,4 @@
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::path::PathBuf;
 use std::process::Command;
@@ -25,15 +26,124 @@ pub fn get_mediainfo(input_file: &PathBuf) -> Value {
     }
 }
 
-pub fn get_audio_ext(track: &Value) -> String {
-    let audio_format = track["Format"]
+/// The properties of a MediaInfo audio track that mkvaudur works with
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AudioTrack {
+    /// Matroska track number
+    pub id: i64,
+    /// 1-based index among the audio tracks of the file
+    pub typeorder: i64,
+    pub format: String,
+    pub format_profile: Option<String>,
+    /// MediaInfo `Format_AdditionalFeatures`, e.g. "XLL" for DTS-HD MA
+    pub additional_features: Option<String>,
+    pub compression_mode: Option<String>,
+    pub language: Option<String>,
+    pub title: Option<String>,
+    #[serde(default)]
+    pub default: bool,
+    #[serde(default)]
+    pub forced: bool,
+    #[serde(default)]
+    pub commentary: bool,
+    #[serde(default)]
+    pub hearing_impaired: bool,
+    #[serde(default)]
+    pub visual_impaired: bool,
+    /// The track is in the original language of the video
+    #[serde(default)]
+    pub original: bool,
+    /// MediaInfo `Encoded_Library`, e.g. "libopus 1.3"
+    pub encoder: Option<String>,
+    /// Start of the track relative to the video, in seconds
+    pub delay: Option<f64>,
+    pub duration: f64,
+    pub channels: Option<i64>,
+    /// MediaInfo channel positions, e.g. "L R C LFE Ls Rs"
+    pub channel_layout: Option<String>,
+    /// In Hz
+    pub sampling_rate: Option<i64>,
+    /// In bits per second
+    pub bit_rate: Option<i64>,
+    /// In bytes
+    pub stream_size: Option<i64>,
+    pub bit_depth: Option<i64>,
+    /// "Little" or "Big", for PCM
+    pub endianness: Option<String>,
+    /// "Signed" or "Unsigned", for PCM
+    pub sign: Option<String>,
+}
+
+impl AudioTrack {
+    pub fn from_mediainfo(track: &Value) -> AudioTrack {
+        AudioTrack {
+            // Elementary audio files have no track ID
+            id: track["ID"]
+                .as_str()
+                .map_or(0, |id| id.parse::<i64>().unwrap()),
+            // MediaInfo omits the typeorder when a file has a single audio track
+            typeorder: track["@typeorder"]
+                .as_str()
+                .map_or(1, |t| t.parse::<i64>().unwrap()),
+            format: track["Format"].as_str().unwrap().to_owned(),
+            format_profile: track["Format_Profile"].as_str().map(str::to_owned),
+            additional_features: track["Format_AdditionalFeatures"]
+                .as_str()
+                .map(str::to_owned),
+            compression_mode: track["Compression_Mode"].as_str().map(str::to_owned),
+            language: track["Language"].as_str().map(str::to_owned),
+            title: track["Title"].as_str().map(str::to_owned),
+            default: track["Default"] == "Yes",
+            forced: track["Forced"] == "Yes",
+            commentary: track["Commentary"] == "Yes" || track["ServiceKind"] == "C",
+            hearing_impaired: track["HearingImpaired"] == "Yes" || track["ServiceKind"] == "HI",
+            visual_impaired: track["VisuallyImpaired"] == "Yes" || track["ServiceKind"] == "VI",
+            original: track["Original"] == "Yes",
+            encoder: track["Encoded_Library"].as_str().map(str::to_owned),
+            delay: track["Video_Delay"]
+                .as_str()
+                .or(track["Delay"].as_str())
+                .and_then(|d| d.parse().ok()),
+            duration: track["Duration"].as_str().unwrap().parse::<f64>().unwrap(),
+            channels: track["Channels"].as_str().and_then(|c| c.parse().ok()),
+            channel_layout: track["ChannelLayout"].as_str().map(str::to_owned),
+            sampling_rate: track["SamplingRate"].as_str().and_then(|s| s.parse().ok()),
+            bit_rate: track["BitRate"].as_str().and_then(|b| b.parse().ok()),
+            stream_size: track["StreamSize"].as_str().and_then(|s| s.parse().ok()),
+            bit_depth: track["BitDepth"].as_str().and_then(|b| b.parse().ok()),
+            endianness: track["Format_Settings_Endianness"]
+                .as_str()
+                .map(str::to_owned),
+            sign: track["Format_Settings_Sign"].as_str().map(str::to_owned),
+        }
+    }
+}
+
+pub fn get_audio_tracks(mediainfo: &Value) -> Vec<AudioTrack> {
+    mediainfo["media"]["track"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|t| t["@type"] == "Audio")
+        .map(AudioTrack::from_mediainfo)
+        .collect()
+}
+
+pub fn get_video_duration(mediainfo: &Value) -> f64 {
+    let video_track: &Value = mediainfo["media"]["track"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|t| t["@type"] == "Video")
+        .collect::<Vec<&Value>>()[0];
+    video_track["Duration"]
         .as_str()
         .unwrap()
-        .parse::<String>()
+        .parse::<f64>()
         .unwrap()
-        .to_lowercase();
-    if audio_format == "pcm" {
-        return ".wav".to_string();
-    }
-    return ".".to_string() + audio_format.as_str();
+}
+
+/// Path of the file MediaInfo describes, as it was passed to MediaInfo
+pub fn get_media_path(mediainfo: &Value) -> Option<PathBuf> {
+    mediainfo["media"]["@ref"].as_str().map(PathBuf::from)
 }
@@ -0,0 +1,572 @@
+use std::{
+    cell::OnceCell,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    analysis::{decode_pcm, measure_peak},
+    codec::{
+        core_filter, encodes_lossless, lossless_policy, output_codec, CodecPolicy, LosslessPolicy,
+    },
+    export::output::{
+        choose_container, create_merged_filepath, create_track_filepath, Container, LargePcm,
+        OutputFormat,
+    },
+    export::template::NameTemplate,
+    mediainfo::{get_audio_tracks, get_media_path, get_video_duration, AudioTrack},
+    sync::{compare_with_reference, ANALYSIS_SAMPLE_RATE, DEFAULT_MAX_OFFSET},
+    TrackFilter,
+};
+
+/// What happens to the duration of a track
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrackAction {
+    /// Export the track without changing its duration
+    Copy,
+    /// Remove the end of the track
+    Trim,
+    /// Append silence to the end of the track
+    Pad,
+    /// Leave the track alone, because trimming it would remove audible audio
+    Skip,
+}
+
+/// How the action is carried out by ffmpeg
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    /// Plain export of the whole track
+    Copy,
+    /// Limit the output duration with `-t`
+    Cut,
+    /// Re-encode the track with the apad filter
+    Apad,
+    /// Concatenate the track with a generated silence file
+    Concat,
+    /// Change the speed of the track to `tempo` times its original speed and
+    /// cut or pad the result to the target duration. Corrects audio that
+    /// drifts out of sync or was sped up for another frame rate.
+    Stretch {
+        tempo: f64,
+        /// Keep the pitch of the track with atempo instead of resampling it
+        #[serde(default)]
+        preserve_pitch: bool,
+    },
+    /// Nothing is exported
+    None,
+}
+
+/// Pairs of frame rates of which a conversion is commonly done by changing the
+/// speed of the video and audio, e.g. 23.976 fps film released as 25 fps PAL
+const FRAME_RATE_PAIRS: [(f64, f64); 3] = [
+    (25.0, 24000.0 / 1001.0),
+    (24.0, 24000.0 / 1001.0),
+    (25.0, 24.0),
+];
+
+/// Maximum relative difference between the speed corrected track duration
+/// and the video duration for a speed change to be recognised
+const SPEED_CHANGE_TOLERANCE: f64 = 0.0002;
+
+/// Minimum difference in seconds between the track and video durations for a
+/// speed change to be recognised, as the ratio of short tracks matches a frame
+/// rate pair by chance
+const MIN_SPEED_CHANGE_DIFFERENCE: f64 = 1.0;
+
+/// The track was sped up or slowed down to match another frame rate
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SpeedChange {
+    /// Frame rate the audio of the track was made for
+    pub audio_fps: f64,
+    pub video_fps: f64,
+}
+
+impl SpeedChange {
+    /// Speed factor that turns the track back into one for the video frame rate
+    pub fn tempo(&self) -> f64 {
+        self.video_fps / self.audio_fps
+    }
+}
+
+/// Recognises a speed change between known frame rates from the ratio of the
+/// track and video durations
+pub fn detect_speed_change(track_duration: f64, video_duration: f64) -> Option<SpeedChange> {
+    if f64::abs(track_duration - video_duration) < MIN_SPEED_CHANGE_DIFFERENCE {
+        return None;
+    }
+    FRAME_RATE_PAIRS
+        .iter()
+        .flat_map(|(a, b)| [(*a, *b), (*b, *a)])
+        .map(|(audio_fps, video_fps)| SpeedChange {
+            audio_fps,
+            video_fps,
+        })
+        .find(|speed_change| {
+            let corrected_duration = track_duration / speed_change.tempo();
+            f64::abs(corrected_duration - video_duration) / video_duration < SPEED_CHANGE_TOLERANCE
+        })
+}
+
+/// What to do when the end that would be trimmed off a track is not silent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum TailCheck {
+    /// Trim anyway, but warn about it
+    #[default]
+    Warn,
+    /// Do not trim the track
+    Refuse,
+    /// Do not measure the loudness of the end
+    Off,
+}
+
+/// Settings that decide how tracks are exported
+#[derive(Debug, Clone)]
+pub struct PlanOptions {
+    /// Directory of the exported tracks, defaults to the directory of the input
+    pub output_dir: Option<OsString>,
+    pub tail_check: TailCheck,
+    /// Peak level in dBFS above which the end of a track is not considered silent
+    pub silence_threshold: f64,
+    /// Time-stretch tracks that drift against the reference audio track at the
+    /// fitted drift rate before trimming or padding them
+    pub stretch: bool,
+    /// Typeorder of the audio track of the reference file that drift is
+    /// measured against
+    pub stretch_against: i64,
+    /// Undo recognised frame rate speed changes before equalizing the duration
+    pub speed_correct: bool,
+    /// Keep the pitch when changing the speed of a track
+    pub preserve_pitch: bool,
+    /// Overrides of the lossless policy of codecs
+    pub lossless_policies: Vec<CodecPolicy>,
+    /// Container of PCM tracks that are too large for WAV
+    pub large_pcm: LargePcm,
+    pub output_format: OutputFormat,
+    pub name_template: NameTemplate,
+    /// Record the changes made to a track in a tag of the exported file
+    pub tag_changes: bool,
+}
+
+pub const DEFAULT_SILENCE_THRESHOLD: f64 = -50.0;
+
+impl Default for PlanOptions {
+    fn default() -> Self {
+        PlanOptions {
+            output_dir: None,
+            tail_check: TailCheck::Warn,
+            silence_threshold: DEFAULT_SILENCE_THRESHOLD,
+            stretch: false,
+            stretch_against: 1,
+            speed_correct: false,
+            preserve_pitch: false,
+            lossless_policies: vec![],
+            large_pcm: LargePcm::default(),
+            output_format: OutputFormat::default(),
+            name_template: NameTemplate::default(),
+            tag_changes: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackPlan {
+    pub track: AudioTrack,
+    pub action: TrackAction,
+    pub strategy: Strategy,
+    pub target_duration: f64,
+    pub output: PathBuf,
+    /// Peak level in dBFS of the audio that trimming removes
+    #[serde(default)]
+    pub tail_peak: Option<f64>,
+    #[serde(default)]
+    pub speed_change: Option<SpeedChange>,
+    /// How the track is encoded if it is lossless and can not be copied
+    #[serde(default)]
+    pub lossless_policy: LosslessPolicy,
+    #[serde(default)]
+    pub container: Container,
+    /// Written to the `MKVAUDUR` tag of the exported file
+    #[serde(default)]
+    pub change_tag: Option<String>,
+}
+
+impl TrackPlan {
+    /// Positive when the track is longer than the target duration
+    pub fn duration_difference(&self, video_duration: f64) -> f64 {
+        self.track.duration - video_duration
+    }
+
+    /// Summary of what the export changes about the track, e.g. "trimmed
+    /// from 7201.344 s to 7200.000 s, converted from MLP FBA to FLAC"
+    pub fn describe_changes(&self) -> Option<String> {
+        let mut changes = vec![];
+        if let Some(speed_change) = self
+            .speed_change
+            .filter(|_| matches!(self.strategy, Strategy::Stretch { .. }))
+        {
+            changes.push(format!(
+                "speed corrected from {} to {} fps",
+                speed_change.audio_fps, speed_change.video_fps
+            ));
+        } else if let Strategy::Stretch { tempo, .. } = self.strategy {
+            changes.push(format!("stretched by {:.6}", tempo));
+        }
+        match self.action {
+            TrackAction::Trim => changes.push(format!(
+                "trimmed from {:.3} s to {:.3} s",
+                self.track.duration, self.target_duration
+            )),
+            TrackAction::Pad => changes.push(format!(
+                "padded from {:.3} s to {:.3} s",
+                self.track.duration, self.target_duration
+            )),
+            TrackAction::Copy | TrackAction::Skip => {}
+        }
+        if core_filter(&self.track, self.lossless_policy).is_some() {
+            changes.push(format!("reduced to the {} core", self.track.format));
+        } else if let Some(codec) = output_codec(&self.track, self.lossless_policy)
+            .filter(|codec| !codec.formats.contains(&self.track.format.as_str()))
+        {
+            changes.push(format!(
+                "converted from {} to {}",
+                self.track.format, codec.formats[0]
+            ));
+        }
+        (!changes.is_empty()).then(|| changes.join(", "))
+    }
+
+    /// Whether the end that is trimmed off was measured to be silent. An
+    /// end that could not be measured does not count as silent.
+    pub fn tail_is_silent(&self, plan_options: &PlanOptions) -> bool {
+        self.tail_peak
+            .is_some_and(|peak| peak <= plan_options.silence_threshold)
+    }
+
+    /// Checks that the action, strategy and target duration agree, as they
+    /// may have been edited by hand
+    pub fn validate(&self) -> Result<(), String> {
+        let target_duration = self.target_duration;
+        if !target_duration.is_finite() || target_duration <= 0.0 {
+            return Err(format!("invalid target duration {}", target_duration));
+        }
+        if let Strategy::Stretch { tempo, .. } = self.strategy {
+            if !tempo.is_finite() || tempo <= 0.0 {
+                return Err(format!("invalid stretch tempo {}", tempo));
+            }
+        }
+        let strategy_matches = match self.action {
+            TrackAction::Copy => self.strategy == Strategy::Copy,
+            TrackAction::Trim => matches!(self.strategy, Strategy::Cut | Strategy::Stretch { .. }),
+            TrackAction::Pad => matches!(
+                self.strategy,
+                Strategy::Apad | Strategy::Concat | Strategy::Stretch { .. }
+            ),
+            TrackAction::Skip => self.strategy == Strategy::None,
+        };
+        if !strategy_matches {
+            return Err(format!(
+                "action {:?} can not be carried out with strategy {:?}",
+                self.action, self.strategy
+            ));
+        }
+        match self.action {
+            TrackAction::Trim if target_duration >= self.track.duration => Err(format!(
+                "target duration {} is not shorter than the track duration {}",
+                target_duration, self.track.duration
+            )),
+            TrackAction::Pad if target_duration <= self.track.duration => Err(format!(
+                "target duration {} is not longer than the track duration {}",
+                target_duration, self.track.duration
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilePlan {
+    pub input: PathBuf,
+    pub video_duration: f64,
+    pub tracks: Vec<TrackPlan>,
+    /// Matroska file the exported tracks are merged into, after which the
+    /// files of the separate tracks are removed
+    #[serde(default)]
+    pub merged_output: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExportPlan {
+    pub files: Vec<FilePlan>,
+}
+
+impl ExportPlan {
+    /// Validates every track plan, see [`TrackPlan::validate`]
+    pub fn validate(&self) -> Result<(), String> {
+        for file_plan in &self.files {
+            for track_plan in &file_plan.tracks {
+                track_plan.validate().map_err(|e| {
+                    format!(
+                        "Invalid plan for track {} of file {}: {}",
+                        track_plan.track.typeorder,
+                        file_plan.input.display(),
+                        e
+                    )
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn matches_filter(
+    track: &AudioTrack,
+    duration_difference: f64,
+    track_filter: &TrackFilter,
+) -> bool {
+    track_filter.selects(track, duration_difference)
+        && f64::abs(duration_difference) > track_filter.treshold
+}
+
+/// Audio track that `--stretch` measures drift against, with its audio
+/// decoded for sync analysis
+struct StretchReference {
+    file: PathBuf,
+    track: AudioTrack,
+    pcm: Vec<i16>,
+}
+
+impl StretchReference {
+    fn decode(
+        mkv_file: &Path,
+        ref_mediainfo: &Value,
+        plan_options: &PlanOptions,
+    ) -> Option<StretchReference> {
+        let file = get_media_path(ref_mediainfo).unwrap_or(mkv_file.to_owned());
+        let Some(track) = get_audio_tracks(ref_mediainfo)
+            .into_iter()
+            .find(|t| t.typeorder == plan_options.stretch_against)
+        else {
+            log::warn!(
+                "File {} has no audio track {} to measure drift against, no track is stretched",
+                file.display(),
+                plan_options.stretch_against
+            );
+            return None;
+        };
+        let pcm = match decode_pcm(&file, &track, ANALYSIS_SAMPLE_RATE) {
+            Ok(pcm) => pcm,
+            Err(e) => {
+                log::warn!("{}, no track is stretched", e);
+                return None;
+            }
+        };
+        Some(StretchReference { file, track, pcm })
+    }
+
+    /// Speed factor at which `track` keeps pace with the reference track, or
+    /// None when it does not drift and only needs to be trimmed or padded
+    fn drift_tempo(&self, mkv_file: &Path, track: &AudioTrack) -> Option<f64> {
+        if self.file == mkv_file && self.track.typeorder == track.typeorder {
+            return None;
+        }
+        let result = compare_with_reference(
+            mkv_file,
+            track.clone(),
+            &self.track,
+            &self.pcm,
+            DEFAULT_MAX_OFFSET,
+        );
+        if let Some(error) = result.error {
+            log::warn!("{}, it is not stretched", error);
+            return None;
+        }
+        let tempo = result.drift_tempo();
+        if tempo.is_none() {
+            log::info!(
+                "Track {} of file {} does not drift against track {} of file {}, it is not stretched",
+                track.typeorder,
+                mkv_file.display(),
+                self.track.typeorder,
+                self.file.display()
+            );
+        }
+        tempo
+    }
+}
+
+fn choose_strategy(
+    track: &AudioTrack,
+    lossless_policy: LosslessPolicy,
+    action: TrackAction,
+    speed_change: Option<SpeedChange>,
+    drift_tempo: Option<f64>,
+    plan_options: &PlanOptions,
+) -> Strategy {
+    let stretch_tempo = match speed_change {
+        Some(speed_change) if plan_options.speed_correct => Some(speed_change.tempo()),
+        _ => drift_tempo,
+    };
+
+    match (action, stretch_tempo) {
+        (TrackAction::Copy, _) => Strategy::Copy,
+        (TrackAction::Skip, _) => Strategy::None,
+        (TrackAction::Trim | TrackAction::Pad, Some(tempo)) => Strategy::Stretch {
+            tempo,
+            preserve_pitch: plan_options.preserve_pitch,
+        },
+        (TrackAction::Trim, None) => match output_codec(track, lossless_policy) {
+            Some(codec) => codec.trim_strategies[0],
+            None => Strategy::Cut,
+        },
+        (TrackAction::Pad, None) => match output_codec(track, lossless_policy) {
+            Some(codec) => codec.pad_strategies[0],
+            None if encodes_lossless(track, lossless_policy) => Strategy::Apad,
+            None => Strategy::Concat,
+        },
+    }
+}
+
+/// Decide for each audio track of `mkv_file` whether and how it is exported.
+/// The video duration is taken from `ref_mediainfo`.
+pub fn plan_file(
+    mkv_file: &Path,
+    mkv_mediainfo: &Value,
+    ref_mediainfo: &Value,
+    track_filter: &TrackFilter,
+    plan_options: &PlanOptions,
+) -> FilePlan {
+    let video_duration = get_video_duration(ref_mediainfo);
+    // Only decoded once a track needs to be compared with it
+    let stretch_reference = OnceCell::new();
+
+    let tracks = get_audio_tracks(mkv_mediainfo)
+        .into_iter()
+        .filter_map(|track| {
+            let duration_difference = track.duration - video_duration;
+            let action = if matches_filter(&track, duration_difference, track_filter) {
+                match duration_difference > 0.0 {
+                    true => TrackAction::Trim,
+                    false => TrackAction::Pad,
+                }
+            } else if track_filter.process_all {
+                TrackAction::Copy
+            } else {
+                return None;
+            };
+
+            let lossless_policy = match plan_options.output_format {
+                OutputFormat::Flac => LosslessPolicy::Flac,
+                OutputFormat::Wav => LosslessPolicy::Pcm,
+                _ => lossless_policy(&track, &plan_options.lossless_policies),
+            };
+            let speed_change = detect_speed_change(track.duration, video_duration);
+            let drift_tempo = match action {
+                TrackAction::Trim | TrackAction::Pad if plan_options.stretch => stretch_reference
+                    .get_or_init(|| {
+                        StretchReference::decode(mkv_file, ref_mediainfo, plan_options)
+                    })
+                    .as_ref()
+                    .and_then(|reference| reference.drift_tempo(mkv_file, &track)),
+                _ => None,
+            };
+            let strategy = choose_strategy(
+                &track,
+                lossless_policy,
+                action,
+                speed_change,
+                drift_tempo,
+                plan_options,
+            );
+            let tail_peak = match (strategy, plan_options.tail_check) {
+                (Strategy::Cut, TailCheck::Warn | TailCheck::Refuse) => {
+                    measure_peak(mkv_file, &track, video_duration)
+                }
+                _ => None,
+            };
+
+            let target_duration = match action {
+                TrackAction::Copy => track.duration,
+                _ => video_duration,
+            };
+            let container = match plan_options.output_format {
+                OutputFormat::Mka | OutputFormat::MkaMulti => Container::Matroska,
+                _ => choose_container(
+                    &track,
+                    lossless_policy,
+                    target_duration,
+                    plan_options.large_pcm,
+                ),
+            };
+
+            let mut track_plan = TrackPlan {
+                strategy,
+                target_duration,
+                output: create_track_filepath(
+                    mkv_file,
+                    &track,
+                    lossless_policy,
+                    container,
+                    &plan_options.name_template,
+                    &plan_options.output_dir,
+                )
+                .into(),
+                action,
+                track,
+                tail_peak,
+                speed_change,
+                lossless_policy,
+                container,
+                change_tag: None,
+            };
+
+            let tail_checked =
+                strategy == Strategy::Cut && plan_options.tail_check != TailCheck::Off;
+            if tail_checked && !track_plan.tail_is_silent(plan_options) {
+                match tail_peak {
+                    Some(peak) => log::warn!(
+                        "The {:.3}s that would be trimmed off track {} of file {} are not silent (peak {:.1} dBFS)",
+                        duration_difference,
+                        track_plan.track.typeorder,
+                        mkv_file.display(),
+                        peak
+                    ),
+                    None => log::warn!(
+                        "Unable to measure the loudness of the {:.3}s that would be trimmed off track {} of file {}, {}",
+                        duration_difference,
+                        track_plan.track.typeorder,
+                        mkv_file.display(),
+                        match plan_options.tail_check {
+                            TailCheck::Refuse => "not trimming it",
+                            _ => "trimming it anyway",
+                        }
+                    ),
+                }
+                if plan_options.tail_check == TailCheck::Refuse {
+                    track_plan.action = TrackAction::Skip;
+                    track_plan.strategy = Strategy::None;
+                }
+            }
+            if plan_options.tag_changes && track_plan.action != TrackAction::Skip {
+                track_plan.change_tag = track_plan.describe_changes();
+            }
+
+            Some(track_plan)
+        })
+        .collect();
+
+    let merged_output = (plan_options.output_format == OutputFormat::MkaMulti)
+        .then(|| create_merged_filepath(mkv_file, &plan_options.output_dir));
+
+    FilePlan {
+        input: mkv_file.to_owned(),
+        video_duration,
+        tracks,
+        merged_output,
+    }
+}
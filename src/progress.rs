@@ -0,0 +1,113 @@
+use std::{
+    collections::BTreeMap,
+    io::{stderr, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Progress of the ffmpeg process exporting a single track
+#[derive(Debug, Clone)]
+pub struct ProgressEvent {
+    pub input: PathBuf,
+    pub typeorder: i64,
+    /// Between 0 and 100
+    pub percentage: f64,
+    /// Processing speed relative to playback speed, e.g. 2.0 for twice as fast
+    pub speed: Option<f64>,
+    pub eta: Option<Duration>,
+    pub finished: bool,
+}
+
+pub type ProgressCallback = Arc<dyn Fn(&ProgressEvent) + Send + Sync>;
+
+/// Turns the raw `-progress` output of ffmpeg into progress events of a track
+pub struct TrackProgress<'a> {
+    pub input: &'a Path,
+    pub typeorder: i64,
+    /// Expected duration of the output in seconds
+    pub duration: f64,
+    pub callback: Option<&'a ProgressCallback>,
+}
+
+impl TrackProgress<'_> {
+    pub fn update(&self, out_time: f64, speed: Option<f64>, finished: bool) {
+        let Some(callback) = self.callback else {
+            return;
+        };
+        let percentage = match finished {
+            true => 100.0,
+            false if self.duration > 0.0 => (out_time / self.duration * 100.0).clamp(0.0, 100.0),
+            false => 0.0,
+        };
+        let eta = speed
+            .filter(|speed| *speed > 0.0)
+            .map(|speed| Duration::from_secs_f64((self.duration - out_time).max(0.0) / speed));
+
+        callback(&ProgressEvent {
+            input: self.input.to_owned(),
+            typeorder: self.typeorder,
+            percentage,
+            speed,
+            eta,
+            finished,
+        });
+    }
+}
+
+const MAX_STATUS_WIDTH: usize = 120;
+
+/// Single status line on stderr listing every running track export
+#[derive(Default)]
+pub struct TerminalProgress {
+    running: Mutex<BTreeMap<(PathBuf, i64), ProgressEvent>>,
+}
+
+impl TerminalProgress {
+    pub fn update(&self, event: &ProgressEvent) {
+        let mut running = self.running.lock().unwrap();
+        let key = (event.input.clone(), event.typeorder);
+        match event.finished {
+            true => running.remove(&key),
+            false => running.insert(key, event.clone()),
+        };
+
+        let status: String = running
+            .values()
+            .map(format_event)
+            .collect::<Vec<String>>()
+            .join(" | ")
+            .chars()
+            .take(MAX_STATUS_WIDTH)
+            .collect();
+        eprint!("\r\u{001b}[K{}", status);
+        let _ = stderr().flush();
+    }
+
+    /// Removes the status line so regular output can be printed
+    pub fn clear(&self) {
+        let _running = self.running.lock().unwrap();
+        eprint!("\r\u{001b}[K");
+        let _ = stderr().flush();
+    }
+}
+
+fn format_event(event: &ProgressEvent) -> String {
+    let file_name = event
+        .input
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let mut status = format!(
+        "{} #{:02} {:.0}%",
+        file_name, event.typeorder, event.percentage
+    );
+    if let Some(speed) = event.speed {
+        status.push_str(&format!(" {:.1}x", speed));
+    }
+    if let Some(eta) = event.eta {
+        let seconds = eta.as_secs();
+        status.push_str(&format!(" ETA {}:{:02}", seconds / 60, seconds % 60));
+    }
+    status
+}
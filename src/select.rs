@@ -0,0 +1,406 @@
+use std::{fmt, str::FromStr};
+
+use regex::Regex;
+
+use crate::{language::normalize_language, mediainfo::AudioTrack};
+
+/// Property of a track that a selection can compare
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Id,
+    Typeorder,
+    /// Normalised ISO 639-2/B language, "und" when MediaInfo reports none
+    Lang,
+    Title,
+    /// MediaInfo format, e.g. "E-AC-3"
+    Codec,
+    Profile,
+    Channels,
+    SamplingRate,
+    BitDepth,
+    /// Duration in seconds
+    Duration,
+    /// Track duration minus video duration in seconds
+    Diff,
+}
+
+const FIELDS: [(&str, Field); 11] = [
+    ("id", Field::Id),
+    ("typeorder", Field::Typeorder),
+    ("lang", Field::Lang),
+    ("title", Field::Title),
+    ("codec", Field::Codec),
+    ("profile", Field::Profile),
+    ("channels", Field::Channels),
+    ("sampling_rate", Field::SamplingRate),
+    ("bit_depth", Field::BitDepth),
+    ("duration", Field::Duration),
+    ("diff", Field::Diff),
+];
+
+impl Field {
+    fn is_text(self) -> bool {
+        matches!(
+            self,
+            Field::Lang | Field::Title | Field::Codec | Field::Profile
+        )
+    }
+}
+
+/// Flag of a track that a selection can test
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flag {
+    Default,
+    Forced,
+    Commentary,
+    HearingImpaired,
+    VisualImpaired,
+    Original,
+}
+
+const FLAGS: [(&str, Flag); 6] = [
+    ("default", Flag::Default),
+    ("forced", Flag::Forced),
+    ("commentary", Flag::Commentary),
+    ("hearing_impaired", Flag::HearingImpaired),
+    ("visual_impaired", Flag::VisualImpaired),
+    ("original", Flag::Original),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// Value of a comparison, numbers for numeric fields
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(f64),
+    Text(String),
+}
+
+/// Parsed `--select` expression, e.g.
+/// `lang in (jpn, eng) and codec != "AC-3" and channels >= 6 and not commentary`
+#[derive(Debug, Clone)]
+pub enum Selection {
+    And(Box<Selection>, Box<Selection>),
+    Or(Box<Selection>, Box<Selection>),
+    Not(Box<Selection>),
+    Flag(Flag),
+    Compare(Field, Comparison, Value),
+    In(Field, Vec<Value>),
+    Matches(Field, Regex),
+}
+
+impl Selection {
+    /// Whether the track is selected. `duration_difference` is the track
+    /// duration minus the video duration.
+    pub fn matches(&self, track: &AudioTrack, duration_difference: f64) -> bool {
+        match self {
+            Selection::And(a, b) => {
+                a.matches(track, duration_difference) && b.matches(track, duration_difference)
+            }
+            Selection::Or(a, b) => {
+                a.matches(track, duration_difference) || b.matches(track, duration_difference)
+            }
+            Selection::Not(a) => !a.matches(track, duration_difference),
+            Selection::Flag(flag) => match flag {
+                Flag::Default => track.default,
+                Flag::Forced => track.forced,
+                Flag::Commentary => track.commentary,
+                Flag::HearingImpaired => track.hearing_impaired,
+                Flag::VisualImpaired => track.visual_impaired,
+                Flag::Original => track.original,
+            },
+            Selection::Compare(field, comparison, value) => {
+                let actual = field_value(*field, track, duration_difference);
+                compare(actual.as_ref(), *comparison, value)
+            }
+            Selection::In(field, values) => {
+                let actual = field_value(*field, track, duration_difference);
+                values
+                    .iter()
+                    .any(|value| compare(actual.as_ref(), Comparison::Eq, value))
+            }
+            Selection::Matches(field, regex) => {
+                match field_value(*field, track, duration_difference) {
+                    Some(Value::Text(text)) => regex.is_match(&text),
+                    _ => false,
+                }
+            }
+        }
+    }
+}
+
+fn field_value(field: Field, track: &AudioTrack, duration_difference: f64) -> Option<Value> {
+    let number = |value: Option<i64>| value.map(|v| Value::Number(v as f64));
+    let text = |value: &Option<String>| value.clone().map(Value::Text);
+    match field {
+        Field::Id => number(Some(track.id)),
+        Field::Typeorder => number(Some(track.typeorder)),
+        Field::Lang => Some(Value::Text(normalize_language(track.language.as_deref()))),
+        Field::Title => text(&track.title),
+        Field::Codec => Some(Value::Text(track.format.clone())),
+        Field::Profile => text(&track.format_profile),
+        Field::Channels => number(track.channels),
+        Field::SamplingRate => number(track.sampling_rate),
+        Field::BitDepth => number(track.bit_depth),
+        Field::Duration => Some(Value::Number(track.duration)),
+        Field::Diff => Some(Value::Number(duration_difference)),
+    }
+}
+
+/// Text is compared case-insensitively. A missing value only satisfies `!=`.
+fn compare(actual: Option<&Value>, comparison: Comparison, expected: &Value) -> bool {
+    let ordering = match (actual, expected) {
+        (Some(Value::Number(a)), Value::Number(b)) => a.partial_cmp(b),
+        (Some(Value::Text(a)), Value::Text(b)) => Some(a.to_lowercase().cmp(&b.to_lowercase())),
+        _ => None,
+    };
+    let Some(ordering) = ordering else {
+        return comparison == Comparison::Ne;
+    };
+    match comparison {
+        Comparison::Eq => ordering.is_eq(),
+        Comparison::Ne => ordering.is_ne(),
+        Comparison::Lt => ordering.is_lt(),
+        Comparison::Le => ordering.is_le(),
+        Comparison::Gt => ordering.is_gt(),
+        Comparison::Ge => ordering.is_ge(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// Bare word: keyword, field, flag, number or unquoted text
+    Word(String),
+    /// Double-quoted text
+    Quoted(String),
+    Operator(&'static str),
+    Open,
+    Close,
+    Comma,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{}", word),
+            Token::Quoted(text) => write!(f, "\"{}\"", text),
+            Token::Operator(operator) => write!(f, "{}", operator),
+            Token::Open => write!(f, "("),
+            Token::Close => write!(f, ")"),
+            Token::Comma => write!(f, ","),
+        }
+    }
+}
+
+const OPERATORS: [&str; 9] = ["==", "!=", "<=", ">=", "!~", "=", "<", ">", "~"];
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut rest = s.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if let Some(operator) = OPERATORS.iter().find(|o| rest.starts_with(**o)) {
+            tokens.push(Token::Operator(operator));
+            rest = &rest[operator.len()..];
+        } else if c == '(' || c == ')' || c == ',' {
+            tokens.push(match c {
+                '(' => Token::Open,
+                ')' => Token::Close,
+                _ => Token::Comma,
+            });
+            rest = &rest[1..];
+        } else if c == '"' {
+            let mut text = String::new();
+            let mut chars = rest[1..].char_indices();
+            let end = loop {
+                match chars.next() {
+                    Some((i, '"')) => break i + 2,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, escaped)) => text.push(escaped),
+                        None => return Err("unterminated string".to_owned()),
+                    },
+                    Some((_, c)) => text.push(c),
+                    None => return Err("unterminated string".to_owned()),
+                }
+            };
+            tokens.push(Token::Quoted(text));
+            rest = &rest[end..];
+        } else {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || "()\",=!<>~".contains(c))
+                .unwrap_or(rest.len());
+            if end == 0 {
+                return Err(format!("unexpected character {}", c));
+            }
+            tokens.push(Token::Word(rest[..end].to_owned()));
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser; `or` binds weaker than `and`, which binds
+/// weaker than `not`
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self
+            .peek()
+            .cloned()
+            .ok_or("unexpected end of expression".to_owned())?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn next_is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next()? {
+            token if token == expected => Ok(()),
+            token => Err(format!("expected {} but found {}", expected, token)),
+        }
+    }
+
+    fn or(&mut self) -> Result<Selection, String> {
+        let mut selection = self.and()?;
+        while self.next_is_keyword("or") {
+            self.position += 1;
+            selection = Selection::Or(Box::new(selection), Box::new(self.and()?));
+        }
+        Ok(selection)
+    }
+
+    fn and(&mut self) -> Result<Selection, String> {
+        let mut selection = self.unary()?;
+        while self.next_is_keyword("and") {
+            self.position += 1;
+            selection = Selection::And(Box::new(selection), Box::new(self.unary()?));
+        }
+        Ok(selection)
+    }
+
+    fn unary(&mut self) -> Result<Selection, String> {
+        if self.next_is_keyword("not") {
+            self.position += 1;
+            return Ok(Selection::Not(Box::new(self.unary()?)));
+        }
+        let name = match self.next()? {
+            Token::Open => {
+                let selection = self.or()?;
+                self.expect(Token::Close)?;
+                return Ok(selection);
+            }
+            Token::Word(name) => name,
+            token => return Err(format!("expected a field or flag but found {}", token)),
+        };
+        if let Some((_, flag)) = FLAGS.iter().find(|(n, _)| name.eq_ignore_ascii_case(n)) {
+            return Ok(Selection::Flag(*flag));
+        }
+        let Some((_, field)) = FIELDS.iter().find(|(n, _)| name.eq_ignore_ascii_case(n)) else {
+            let names: Vec<&str> = FIELDS
+                .iter()
+                .map(|(n, _)| *n)
+                .chain(FLAGS.iter().map(|(n, _)| *n))
+                .collect();
+            return Err(format!(
+                "unknown field or flag {}, expected one of {}",
+                name,
+                names.join(", ")
+            ));
+        };
+        self.condition(*field)
+    }
+
+    fn condition(&mut self, field: Field) -> Result<Selection, String> {
+        let operator = match self.next()? {
+            Token::Word(word) if word.eq_ignore_ascii_case("in") => {
+                self.expect(Token::Open)?;
+                let mut values = vec![self.value(field)?];
+                while self.peek() == Some(&Token::Comma) {
+                    self.position += 1;
+                    values.push(self.value(field)?);
+                }
+                self.expect(Token::Close)?;
+                return Ok(Selection::In(field, values));
+            }
+            Token::Operator(operator) => operator,
+            token => return Err(format!("expected an operator but found {}", token)),
+        };
+        if operator == "~" || operator == "!~" {
+            if !field.is_text() {
+                return Err(format!("{} only applies to text fields", operator));
+            }
+            let pattern = match self.next()? {
+                Token::Word(pattern) | Token::Quoted(pattern) => pattern,
+                token => return Err(format!("expected a regex but found {}", token)),
+            };
+            let regex = Regex::new(&pattern).map_err(|e| e.to_string())?;
+            let selection = Selection::Matches(field, regex);
+            return Ok(match operator {
+                "~" => selection,
+                _ => Selection::Not(Box::new(selection)),
+            });
+        }
+        let comparison = match operator {
+            "=" | "==" => Comparison::Eq,
+            "!=" => Comparison::Ne,
+            "<" => Comparison::Lt,
+            "<=" => Comparison::Le,
+            ">" => Comparison::Gt,
+            _ => Comparison::Ge,
+        };
+        if field.is_text() && !matches!(comparison, Comparison::Eq | Comparison::Ne) {
+            return Err(format!("{} does not apply to text fields", operator));
+        }
+        Ok(Selection::Compare(field, comparison, self.value(field)?))
+    }
+
+    fn value(&mut self, field: Field) -> Result<Value, String> {
+        let text = match self.next()? {
+            Token::Word(text) | Token::Quoted(text) => text,
+            token => return Err(format!("expected a value but found {}", token)),
+        };
+        match field.is_text() {
+            true if field == Field::Lang => Ok(Value::Text(normalize_language(Some(&text)))),
+            true => Ok(Value::Text(text)),
+            false => text
+                .parse()
+                .map(Value::Number)
+                .map_err(|_| format!("expected a number but found {}", text)),
+        }
+    }
+}
+
+impl FromStr for Selection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            position: 0,
+        };
+        let selection = parser.or()?;
+        match parser.peek() {
+            None => Ok(selection),
+            Some(token) => Err(format!("unexpected {} after the expression", token)),
+        }
+    }
+}
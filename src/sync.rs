@@ -0,0 +1,341 @@
+use std::{fmt, path::Path};
+
+use rustfft::{num_complex::Complex, FftPlanner};
+use serde_json::Value;
+
+use crate::{
+    analysis::decode_pcm,
+    mediainfo::{get_audio_tracks, get_video_duration, AudioTrack},
+    TrackFilter,
+};
+
+/// Tracks are decoded at a low sample rate, which is plenty to line up
+/// speech, music and effects and keeps long tracks small in memory
+pub const ANALYSIS_SAMPLE_RATE: u32 = 4000;
+
+/// Duration in seconds of each compared window
+const WINDOW_DURATION: f64 = 10.0;
+
+/// Maximum number of windows compared per track
+const MAX_WINDOWS: usize = 50;
+
+/// Windows quieter than this RMS level (in 16-bit sample units) are skipped
+const MIN_WINDOW_RMS: f64 = 100.0;
+
+/// Minimum normalized correlation for a window offset to be trusted
+const MIN_CONFIDENCE: f64 = 0.3;
+
+/// Offsets that vary more than this many seconds over a track indicate drift
+pub const DRIFT_TOLERANCE: f64 = 0.04;
+
+pub const DEFAULT_MAX_OFFSET: f64 = 5.0;
+
+/// Offset of one window of a track relative to the reference track
+#[derive(Debug, Clone)]
+pub struct WindowOffset {
+    /// Start of the window in the track, in seconds
+    pub position: f64,
+    /// Positive when the audio of the track is later than the reference
+    pub offset: f64,
+    /// Normalized cross-correlation between 0 and 1
+    pub confidence: f64,
+}
+
+/// Cross-correlates windows spread over `track` with `reference` and returns
+/// the offset of every window that is loud enough to compare
+pub fn correlate_windows(
+    track: &[i16],
+    reference: &[i16],
+    sample_rate: u32,
+    max_offset: f64,
+) -> Vec<WindowOffset> {
+    let window_len = (WINDOW_DURATION * sample_rate as f64) as usize;
+    let max_lag = (max_offset * sample_rate as f64) as usize;
+    let segment_len = window_len + 2 * max_lag;
+    if track.len() < window_len || reference.len() < segment_len {
+        return vec![];
+    }
+
+    // The reference segment spans max_lag samples on both sides of the window
+    let first_start = max_lag;
+    let Some(last_start) = usize::min(
+        track.len().saturating_sub(window_len),
+        reference.len().saturating_sub(window_len + max_lag),
+    )
+    .checked_sub(first_start) else {
+        return vec![];
+    };
+    let step = usize::max(last_start / MAX_WINDOWS.max(1), window_len);
+
+    let fft_len = (window_len + segment_len).next_power_of_two();
+    let mut planner = FftPlanner::<f32>::new();
+    let fft = planner.plan_fft_forward(fft_len);
+    let ifft = planner.plan_fft_inverse(fft_len);
+
+    let mut offsets = vec![];
+    for start in (first_start..=first_start + last_start).step_by(step) {
+        let window = &track[start..start + window_len];
+        let segment = &reference[start - max_lag..start - max_lag + segment_len];
+
+        let window_energy: f64 = window.iter().map(|s| (*s as f64).powi(2)).sum();
+        if (window_energy / window_len as f64).sqrt() < MIN_WINDOW_RMS {
+            continue;
+        }
+
+        let mut window_spectrum = to_complex(window, fft_len);
+        let mut correlation = to_complex(segment, fft_len);
+        fft.process(&mut window_spectrum);
+        fft.process(&mut correlation);
+        for (c, w) in correlation.iter_mut().zip(&window_spectrum) {
+            *c *= w.conj();
+        }
+        ifft.process(&mut correlation);
+
+        // Energy of every window-sized part of the segment, for normalization
+        let mut segment_energy = vec![0.0; segment_len + 1];
+        for (i, sample) in segment.iter().enumerate() {
+            segment_energy[i + 1] = segment_energy[i] + (*sample as f64).powi(2);
+        }
+
+        let (best_lag, confidence) = (0..=2 * max_lag)
+            .map(|lag| {
+                let energy = segment_energy[lag + window_len] - segment_energy[lag];
+                let value = correlation[lag].re as f64 / fft_len as f64;
+                (
+                    lag,
+                    value / (window_energy * energy).sqrt().max(f64::EPSILON),
+                )
+            })
+            .fold((0, f64::MIN), |best, current| match current.1 > best.1 {
+                true => current,
+                false => best,
+            });
+
+        offsets.push(WindowOffset {
+            position: start as f64 / sample_rate as f64,
+            offset: (max_lag as f64 - best_lag as f64) / sample_rate as f64,
+            confidence,
+        });
+    }
+    offsets
+}
+
+fn to_complex(samples: &[i16], len: usize) -> Vec<Complex<f32>> {
+    let mut buffer: Vec<Complex<f32>> = samples
+        .iter()
+        .map(|s| Complex::new(*s as f32, 0.0))
+        .collect();
+    buffer.resize(len, Complex::new(0.0, 0.0));
+    buffer
+}
+
+fn percentile(sorted: &[f64], fraction: f64) -> f64 {
+    sorted[((sorted.len() - 1) as f64 * fraction).round() as usize]
+}
+
+/// Offset that changes at a constant rate over the duration of a track
+#[derive(Debug, Clone, Copy)]
+pub struct LinearDrift {
+    /// Offset in seconds at the start of the track
+    pub initial_offset: f64,
+    /// Change of the offset in seconds per second of track
+    pub rate: f64,
+}
+
+impl LinearDrift {
+    pub fn offset_at(&self, position: f64) -> f64 {
+        self.initial_offset + self.rate * position
+    }
+}
+
+/// Result of comparing an audio track with a reference audio track
+#[derive(Debug, Clone)]
+pub struct SyncResult {
+    pub track: AudioTrack,
+    pub reference_track: AudioTrack,
+    pub windows: Vec<WindowOffset>,
+    /// Why the tracks could not be compared, in which case there are no
+    /// windows
+    pub error: Option<String>,
+}
+
+impl SyncResult {
+    pub fn confident_windows(&self) -> Vec<&WindowOffset> {
+        self.windows
+            .iter()
+            .filter(|w| w.confidence >= MIN_CONFIDENCE)
+            .collect()
+    }
+
+    fn sorted_offsets(&self) -> Vec<f64> {
+        let mut offsets: Vec<f64> = self.confident_windows().iter().map(|w| w.offset).collect();
+        offsets.sort_by(f64::total_cmp);
+        offsets
+    }
+
+    /// Median offset of the confident windows
+    pub fn offset(&self) -> Option<f64> {
+        let offsets = self.sorted_offsets();
+        (!offsets.is_empty()).then(|| percentile(&offsets, 0.5))
+    }
+
+    /// Range of the offsets, ignoring the 10% most extreme windows on each side
+    pub fn offset_range(&self) -> Option<(f64, f64)> {
+        let offsets = self.sorted_offsets();
+        (!offsets.is_empty()).then(|| (percentile(&offsets, 0.1), percentile(&offsets, 0.9)))
+    }
+
+    /// Least squares fit of the offsets of the confident windows
+    pub fn linear_drift(&self) -> Option<LinearDrift> {
+        let windows = self.confident_windows();
+        if windows.len() < 3 {
+            return None;
+        }
+        let count = windows.len() as f64;
+        let mean_position = windows.iter().map(|w| w.position).sum::<f64>() / count;
+        let mean_offset = windows.iter().map(|w| w.offset).sum::<f64>() / count;
+        let covariance: f64 = windows
+            .iter()
+            .map(|w| (w.position - mean_position) * (w.offset - mean_offset))
+            .sum();
+        let variance: f64 = windows
+            .iter()
+            .map(|w| (w.position - mean_position).powi(2))
+            .sum();
+        if variance <= 0.0 {
+            return None;
+        }
+        let rate = covariance / variance;
+        Some(LinearDrift {
+            initial_offset: mean_offset - rate * mean_position,
+            rate,
+        })
+    }
+
+    pub fn has_drift(&self) -> bool {
+        self.offset_range()
+            .is_some_and(|(min, max)| max - min > DRIFT_TOLERANCE)
+    }
+
+    /// Speed factor that makes the track keep pace with the reference track,
+    /// from the fitted drift. None when the track does not drift.
+    pub fn drift_tempo(&self) -> Option<f64> {
+        match self.has_drift() {
+            // A track that falls behind by `rate` seconds per second plays
+            // `1 - rate` seconds of the reference per second
+            true => self.linear_drift().map(|drift| 1.0 / (1.0 - drift.rate)),
+            false => None,
+        }
+    }
+}
+
+impl fmt::Display for SyncResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Track {:02} ({}) against track {:02} ({}): ",
+            self.track.typeorder,
+            self.track.language.as_deref().unwrap_or("und"),
+            self.reference_track.typeorder,
+            self.reference_track.language.as_deref().unwrap_or("und"),
+        )?;
+        if let Some(error) = &self.error {
+            return write!(f, "{}", error);
+        }
+        let Some(offset) = self.offset() else {
+            return write!(f, "no reliable offset found");
+        };
+        let confident_windows = self.confident_windows();
+        write!(
+            f,
+            "offset {:+.3}s (confidence {:.2}, {} of {} windows)",
+            offset,
+            confident_windows.iter().map(|w| w.confidence).sum::<f64>()
+                / confident_windows.len() as f64,
+            confident_windows.len(),
+            self.windows.len()
+        )?;
+        if let (true, Some((min, max))) = (self.has_drift(), self.offset_range()) {
+            write!(
+                f,
+                "\n  Drift: the offset varies from {:+.3}s to {:+.3}s",
+                min, max
+            )?;
+            if let Some(drift) = self.linear_drift() {
+                write!(
+                    f,
+                    "\n  Linear fit: {:+.1} ms per minute, {:+.3}s at the start and {:+.3}s at the end; \
+                     export with --stretch to correct it",
+                    drift.rate * 60_000.0,
+                    drift.initial_offset,
+                    drift.offset_at(self.track.duration)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compares every selected audio track of `mkv_file` with the audio track
+/// with typeorder `against` of the reference file. Without a reference file,
+/// the tracks are compared with a track of the same file.
+pub fn sync_check(
+    mkv_file: &Path,
+    mkv_mediainfo: &Value,
+    reference: Option<(&Path, &Value)>,
+    track_filter: &TrackFilter,
+    against: i64,
+    max_offset: f64,
+) -> Vec<SyncResult> {
+    let (ref_file, ref_mediainfo) = reference.unwrap_or((mkv_file, mkv_mediainfo));
+    let Some(reference_track) = get_audio_tracks(ref_mediainfo)
+        .into_iter()
+        .find(|t| t.typeorder == against)
+    else {
+        log::error!("File {} has no audio track {}", ref_file.display(), against);
+        return vec![];
+    };
+    let reference_pcm = decode_pcm(ref_file, &reference_track, ANALYSIS_SAMPLE_RATE);
+
+    let video_duration = get_video_duration(mkv_mediainfo);
+    get_audio_tracks(mkv_mediainfo)
+        .into_iter()
+        .filter(|track| track_filter.selects(track, track.duration - video_duration))
+        .filter(|track| reference.is_some() || track.typeorder != against)
+        .map(|track| match &reference_pcm {
+            Ok(reference_pcm) => {
+                compare_with_reference(mkv_file, track, &reference_track, reference_pcm, max_offset)
+            }
+            Err(e) => SyncResult {
+                track,
+                reference_track: reference_track.clone(),
+                windows: vec![],
+                error: Some(e.clone()),
+            },
+        })
+        .collect()
+}
+
+/// Compares `track` of `mkv_file` with the reference track, of which
+/// `reference_pcm` is decoded at [`ANALYSIS_SAMPLE_RATE`]
+pub fn compare_with_reference(
+    mkv_file: &Path,
+    track: AudioTrack,
+    reference_track: &AudioTrack,
+    reference_pcm: &[i16],
+    max_offset: f64,
+) -> SyncResult {
+    let (windows, error) = match decode_pcm(mkv_file, &track, ANALYSIS_SAMPLE_RATE) {
+        Ok(track_pcm) => (
+            correlate_windows(&track_pcm, reference_pcm, ANALYSIS_SAMPLE_RATE, max_offset),
+            None,
+        ),
+        Err(e) => (vec![], Some(e)),
+    };
+    SyncResult {
+        track,
+        reference_track: reference_track.clone(),
+        windows,
+        error,
+    }
+}
@@ -0,0 +1,55 @@
+use std::path::Path;
+
+use mkvaudur::{export::append::concat_script, mediainfo::AudioTrack};
+
+#[test]
+fn concat_scripts() {
+    let track = AudioTrack {
+        id: 3,
+        typeorder: 2,
+        format: "AC-3".to_owned(),
+        duration: 1.5,
+        ..Default::default()
+    };
+    let input_file = Path::new("/videos/film.mkv");
+    let silence_file = Path::new("/tmp/film.silence.mka");
+    assert_eq!(
+        concat_script(input_file, silence_file, &track),
+        "ffconcat version 1.0\n\
+         stream\n\
+         exact_stream_id 3\n\
+         file '/videos/film.mkv'\n\
+         duration 1.5\n\
+         file '/tmp/film.silence.mka'"
+    );
+
+    // The delay is skipped, so the silence starts where the track ends
+    let delayed = AudioTrack {
+        delay: Some(0.25),
+        ..track.clone()
+    };
+    assert_eq!(
+        concat_script(input_file, silence_file, &delayed),
+        "ffconcat version 1.0\n\
+         stream\n\
+         exact_stream_id 3\n\
+         file '/videos/film.mkv'\n\
+         inpoint 0.25\n\
+         duration 1.5\n\
+         file '/tmp/film.silence.mka'"
+    );
+
+    // Elementary streams have no track number to select
+    let elementary = AudioTrack { id: 0, ..track };
+    assert_eq!(
+        concat_script(
+            Path::new("/videos/Director's Cut.ac3"),
+            Path::new("/tmp/silence.ac3"),
+            &elementary
+        ),
+        "ffconcat version 1.0\n\
+         file '/videos/Director'\\''s Cut.ac3'\n\
+         duration 1.5\n\
+         file '/tmp/silence.ac3'"
+    );
+}
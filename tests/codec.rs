@@ -0,0 +1,213 @@
+use mkvaudur::{
+    codec::{
+        compression, core_filter, encodes_lossless, get_audio_ext, lossless_encoder_args,
+        lossless_policy, Codec, Compression, LosslessPolicy,
+    },
+    export::output::{ffmpeg_channel_layout, get_filter_args, get_reencode_args},
+    mediainfo::AudioTrack,
+    plan::Strategy,
+};
+
+fn audio_track(format: &str, format_profile: Option<&str>) -> AudioTrack {
+    AudioTrack {
+        id: 1,
+        typeorder: 1,
+        format: format.to_owned(),
+        format_profile: format_profile.map(str::to_owned),
+        duration: 1.0,
+        channels: Some(2),
+        sampling_rate: Some(48000),
+        ..Default::default()
+    }
+}
+
+fn get_audio_ext_keep(track: &AudioTrack) -> String {
+    get_audio_ext(track, LosslessPolicy::Keep)
+}
+
+#[test]
+fn extensions() {
+    assert_eq!(get_audio_ext_keep(&audio_track("E-AC-3", None)), ".eac3");
+    assert_eq!(get_audio_ext_keep(&audio_track("PCM", None)), ".wav");
+    assert_eq!(
+        get_audio_ext_keep(&audio_track("MPEG Audio", Some("Layer 3"))),
+        ".mp3"
+    );
+    assert_eq!(
+        get_audio_ext_keep(&audio_track("MPEG Audio", Some("Layer 2"))),
+        ".mp2"
+    );
+    assert_eq!(get_audio_ext_keep(&audio_track("MLP FBA", None)), ".thd");
+    assert_eq!(
+        get_audio_ext_keep(&audio_track("Some Codec", None)),
+        ".somecodec"
+    );
+}
+
+#[test]
+fn strategies() {
+    let aac = Codec::for_track(&audio_track("AAC", None)).unwrap();
+    assert_eq!(aac.muxer, "adts");
+    assert!(!aac.lossless);
+    assert_eq!(aac.pad_strategies[0], Strategy::Concat);
+
+    let flac = Codec::for_track(&audio_track("FLAC", None)).unwrap();
+    assert!(flac.lossless);
+    assert_eq!(flac.pad_strategies, &[Strategy::Apad]);
+
+    assert!(Codec::for_track(&audio_track("MPEG Audio", None)).is_none());
+}
+
+#[test]
+fn lossless_policies() {
+    let mut truehd = audio_track("MLP FBA", None);
+    truehd.compression_mode = Some("Lossless".to_owned());
+    truehd.bit_depth = Some(24);
+    let policy = lossless_policy(&truehd, &[]);
+    assert_eq!(policy, LosslessPolicy::Flac);
+    assert_eq!(get_audio_ext(&truehd, policy), ".flac");
+    assert_eq!(
+        lossless_encoder_args(&truehd, policy),
+        vec![
+            "-c:a",
+            "flac",
+            "-sample_fmt",
+            "s32",
+            "-bits_per_raw_sample",
+            "24"
+        ]
+    );
+    let keep = vec!["truehd=keep".parse().unwrap()];
+    assert_eq!(lossless_policy(&truehd, &keep), LosslessPolicy::Keep);
+
+    let mut pcm = audio_track("PCM", None);
+    pcm.compression_mode = Some("Lossless".to_owned());
+    pcm.bit_depth = Some(24);
+    pcm.endianness = Some("Big".to_owned());
+    let policy = lossless_policy(&pcm, &[]);
+    assert_eq!(policy, LosslessPolicy::Keep);
+    assert_eq!(
+        lossless_encoder_args(&pcm, policy),
+        vec!["-c:a", "pcm_s24le"]
+    );
+}
+
+#[test]
+fn hybrid_tracks() {
+    let mut dts_ma = audio_track("DTS", None);
+    dts_ma.additional_features = Some("XLL".to_owned());
+    dts_ma.compression_mode = Some("Lossless / Lossy".to_owned());
+    assert_eq!(compression(&dts_ma), Compression::Hybrid);
+    assert_eq!(lossless_policy(&dts_ma, &[]), LosslessPolicy::Flac);
+    assert_eq!(get_audio_ext(&dts_ma, LosslessPolicy::Flac), ".flac");
+
+    let core = vec!["dts=core".parse().unwrap()];
+    let policy = lossless_policy(&dts_ma, &core);
+    assert_eq!(policy, LosslessPolicy::Core);
+    assert!(!encodes_lossless(&dts_ma, policy));
+    assert_eq!(core_filter(&dts_ma, policy), Some("dca_core"));
+    assert_eq!(get_audio_ext(&dts_ma, policy), ".dts");
+
+    // DTS-HD MA reported as plain lossless still has its core extracted
+    let mut lossless_dts_ma = audio_track("DTS", None);
+    lossless_dts_ma.additional_features = Some("XLL".to_owned());
+    lossless_dts_ma.compression_mode = Some("Lossless".to_owned());
+    assert_eq!(compression(&lossless_dts_ma), Compression::Hybrid);
+    let policy = lossless_policy(&lossless_dts_ma, &core);
+    assert_eq!(policy, LosslessPolicy::Core);
+    assert_eq!(core_filter(&lossless_dts_ma, policy), Some("dca_core"));
+
+    // ffmpeg can not extract the AC-3 core of TrueHD
+    let mut truehd = audio_track("MLP FBA", None);
+    truehd.compression_mode = Some("Lossless / Lossy".to_owned());
+    let core = vec!["truehd=core".parse().unwrap()];
+    assert_eq!(lossless_policy(&truehd, &core), LosslessPolicy::Flac);
+
+    // Older MediaInfo versions report DTS-HD MA without the XLL feature
+    let mut old_dts_ma = audio_track("DTS", Some("MA / Core"));
+    old_dts_ma.compression_mode = Some("Lossless / Lossy".to_owned());
+    assert!(Codec::for_track(&old_dts_ma).is_some_and(|codec| codec.lossless));
+    assert_eq!(lossless_policy(&old_dts_ma, &[]), LosslessPolicy::Flac);
+    assert_eq!(
+        lossless_encoder_args(&old_dts_ma, LosslessPolicy::Flac)[1],
+        "flac"
+    );
+
+    // Hybrid tracks of codecs not known to be lossless are never re-encoded
+    // with their own encoder by default
+    let mut hybrid = audio_track("DTS", Some("Express"));
+    hybrid.compression_mode = Some("Lossless / Lossy".to_owned());
+    assert_eq!(lossless_policy(&hybrid, &[]), LosslessPolicy::Flac);
+    let mut hybrid = audio_track("Some Codec", None);
+    hybrid.compression_mode = Some("Lossless / Lossy".to_owned());
+    assert_eq!(lossless_policy(&hybrid, &[]), LosslessPolicy::Flac);
+
+    let unknown = audio_track("Some Codec", None);
+    assert_eq!(compression(&unknown), Compression::Lossy);
+}
+
+#[test]
+fn channel_layouts() {
+    let layout = |channels: Option<i64>, channel_layout: Option<&str>| {
+        let mut track = audio_track("DTS", None);
+        track.channels = channels;
+        track.channel_layout = channel_layout.map(str::to_owned);
+        ffmpeg_channel_layout(&track)
+    };
+
+    assert_eq!(
+        layout(Some(6), Some("C L R Ls Rs LFE")),
+        Some("FL+FR+FC+LFE+SL+SR".to_owned())
+    );
+    assert_eq!(
+        layout(Some(8), Some("L R C LFE Ls Rs Lb Rb")),
+        Some("FL+FR+FC+LFE+BL+BR+SL+SR".to_owned())
+    );
+    assert_eq!(layout(Some(1), Some("M")), Some("FC".to_owned()));
+    // Unknown, unusable and mismatching layouts fall back to the channel count
+    assert_eq!(layout(Some(6), None), Some("5.1".to_owned()));
+    assert_eq!(layout(Some(2), Some("unknown")), Some("stereo".to_owned()));
+    assert_eq!(layout(Some(8), Some("L R C LFE")), Some("7.1".to_owned()));
+    assert_eq!(
+        layout(Some(12), Some("Object Based")),
+        Some("12c".to_owned())
+    );
+    assert_eq!(layout(None, Some("L R")), None);
+}
+
+#[test]
+fn pcm_channel_filter() {
+    // DTS-HD MA decoded to PCM, with the layout in DTS bitstream order
+    let mut dts_ma = audio_track("DTS", Some("MA"));
+    dts_ma.additional_features = Some("XLL".to_owned());
+    dts_ma.compression_mode = Some("Lossless / Lossy".to_owned());
+    dts_ma.channels = Some(6);
+    dts_ma.channel_layout = Some("C L R Ls Rs LFE".to_owned());
+    assert_eq!(
+        get_filter_args(&dts_ma, LosslessPolicy::Pcm, Some("apad".to_owned())),
+        vec!["-af", "aformat=channel_layouts=FL+FR+FC+LFE+SL+SR,apad"]
+    );
+    // Only PCM output gets the layout
+    assert_eq!(
+        get_filter_args(&dts_ma, LosslessPolicy::Flac, None),
+        Vec::<String>::new()
+    );
+}
+
+#[test]
+fn lossy_reencode_args() {
+    // Lossy tracks keep their codec instead of getting the muxer default
+    let aac = AudioTrack {
+        bit_rate: Some(192000),
+        ..audio_track("AAC", Some("LC"))
+    };
+    assert_eq!(
+        get_reencode_args(&aac, LosslessPolicy::default()),
+        vec!["-c:a", "aac", "-strict", "experimental", "-b:a", "192000"]
+    );
+    let dts = audio_track("DTS", None);
+    assert_eq!(
+        get_reencode_args(&dts, LosslessPolicy::default()),
+        vec!["-c:a", "dca", "-strict", "experimental"]
+    );
+}
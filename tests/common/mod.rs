@@ -0,0 +1,62 @@
+//! Fixtures shared by the integration tests
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+
+use serde_json::{json, Value};
+
+use mkvaudur::{
+    plan::{plan_file, FilePlan, PlanOptions, TailCheck},
+    TrackFilter,
+};
+
+/// Input file of the fixtures, a video of 2 s
+pub const TEST_VIDEO: &str = "./tests/test_video_2s.mkv";
+
+/// MediaInfo output of [`TEST_VIDEO`] with the given audio tracks, which are
+/// numbered in order
+pub fn mediainfo_fixture(audio_tracks: &[Value]) -> Value {
+    let mut tracks = vec![
+        json!({ "@type": "General" }),
+        json!({ "@type": "Video", "ID": "1", "Duration": "2.000" }),
+    ];
+    for (index, audio_track) in audio_tracks.iter().enumerate() {
+        let mut track = json!({
+            "@type": "Audio",
+            "@typeorder": (index + 1).to_string(),
+            "ID": (index + 2).to_string()
+        });
+        track
+            .as_object_mut()
+            .unwrap()
+            .extend(audio_track.as_object().unwrap().clone());
+        tracks.push(track);
+    }
+    json!({ "media": { "@ref": TEST_VIDEO, "track": tracks } })
+}
+
+/// Selects every track, and trims or pads those that differ more than 0.1 s
+/// from the video
+pub fn all_tracks() -> TrackFilter {
+    TrackFilter {
+        treshold: 0.1,
+        language: None,
+        process_all: true,
+        selection: None,
+    }
+}
+
+/// Plans all tracks of `mediainfo` as [`TEST_VIDEO`], without the tail check
+/// as that runs ffmpeg
+pub fn plan_fixture(mediainfo: &Value, plan_options: PlanOptions) -> FilePlan {
+    plan_file(
+        &PathBuf::from(TEST_VIDEO),
+        mediainfo,
+        mediainfo,
+        &all_tracks(),
+        &PlanOptions {
+            tail_check: TailCheck::Off,
+            ..plan_options
+        },
+    )
+}
@@ -0,0 +1,91 @@
+mod common;
+
+use std::{
+    fs::write,
+    path::{Path, PathBuf},
+};
+
+use serde_json::json;
+
+use common::{mediainfo_fixture, plan_fixture};
+use mkvaudur::{
+    export::{
+        conflict::{resolve_conflicts, OnConflict},
+        template::NameTemplate,
+    },
+    plan::{FilePlan, PlanOptions, TrackAction},
+};
+
+/// Plan whose two Opus tracks share the output `<dir>/test_video_2s.opus`
+fn colliding_plan(output_dir: &Path) -> FilePlan {
+    plan_fixture(
+        &mediainfo_fixture(&[
+            json!({
+                "Format": "Opus", "Compression_Mode": "Lossy", "Language": "en",
+                "Duration": "2.000"
+            }),
+            json!({
+                "Format": "Opus", "Compression_Mode": "Lossy", "Language": "ja",
+                "Duration": "2.000"
+            }),
+        ]),
+        PlanOptions {
+            output_dir: Some(output_dir.as_os_str().to_owned()),
+            name_template: "{stem}{ext}".parse::<NameTemplate>().unwrap(),
+            ..Default::default()
+        },
+    )
+}
+
+#[test]
+fn duplicate_outputs() {
+    let output_dir = tempfile::tempdir().unwrap();
+    let file_plans = [colliding_plan(output_dir.path())];
+    let output = output_dir.path().join("test_video_2s.opus");
+
+    for on_conflict in [OnConflict::Overwrite, OnConflict::Error] {
+        let error = resolve_conflicts(&file_plans, on_conflict, |_, _| false).unwrap_err();
+        assert!(error.contains("is the output of several tracks"));
+    }
+
+    let renamed = resolve_conflicts(&file_plans, OnConflict::Rename, |_, _| false).unwrap();
+    let outputs: Vec<PathBuf> = renamed[0].tracks.iter().map(|t| t.output.clone()).collect();
+    assert_eq!(
+        outputs,
+        vec![
+            output.clone(),
+            output_dir.path().join("test_video_2s_2.opus")
+        ]
+    );
+
+    let skipped = resolve_conflicts(&file_plans, OnConflict::Skip, |_, _| false).unwrap();
+    let actions: Vec<TrackAction> = skipped[0].tracks.iter().map(|t| t.action).collect();
+    assert_eq!(actions, vec![TrackAction::Copy, TrackAction::Skip]);
+}
+
+#[test]
+fn existing_outputs() {
+    let output_dir = tempfile::tempdir().unwrap();
+    let mut file_plan = colliding_plan(output_dir.path());
+    file_plan.tracks.truncate(1);
+    let file_plans = [file_plan];
+    let output = output_dir.path().join("test_video_2s.opus");
+    write(&output, "").unwrap();
+    write(output_dir.path().join("test_video_2s_2.opus"), "").unwrap();
+
+    let overwritten = resolve_conflicts(&file_plans, OnConflict::Overwrite, |_, _| false).unwrap();
+    assert_eq!(overwritten[0].tracks[0].output, output);
+
+    let error = resolve_conflicts(&file_plans, OnConflict::Error, |_, _| false).unwrap_err();
+    assert!(error.contains("already exists"));
+
+    // Outputs of an earlier, cancelled run are resumed rather than renamed
+    let resumed = resolve_conflicts(&file_plans, OnConflict::Error, |_, _| true).unwrap();
+    assert_eq!(resumed[0].tracks[0].output, output);
+
+    let renamed = resolve_conflicts(&file_plans, OnConflict::Rename, |_, _| false).unwrap();
+    assert_eq!(
+        renamed[0].tracks[0].output,
+        output_dir.path().join("test_video_2s_3.opus")
+    );
+}
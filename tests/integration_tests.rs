@@ -4,7 +4,9 @@ use std::{
     path::PathBuf,
 };
 
-use mkvaudur::{args::OperationMode, mediainfo::get_mediainfo, process_mkv_file, TrackFilter};
+use mkvaudur::{
+    args::OperationMode, mediainfo::get_mediainfo, plan::PlanOptions, process_mkv_file, TrackFilter,
+};
 
 fn get_audio_files(dir: &str) -> Vec<PathBuf> {
     let paths = read_dir(dir).unwrap();
@@ -22,7 +24,7 @@ fn get_audio_files(dir: &str) -> Vec<PathBuf> {
 }
 
 fn get_audio_file_duration(audio_file: &PathBuf) -> f64 {
-    let audio_mediainfo = get_mediainfo(&audio_file);
+    let audio_mediainfo = get_mediainfo(audio_file);
     audio_mediainfo["media"]["track"].as_array().unwrap()[0]["Duration"]
         .as_str()
         .unwrap()
@@ -34,6 +36,7 @@ static TRACK_FILTER: TrackFilter = TrackFilter {
     treshold: 0.0,
     language: None,
     process_all: false,
+    selection: None,
 };
 
 #[test]
@@ -46,7 +49,10 @@ fn trim() {
         &mkv_mediainfo,
         &OperationMode::Export,
         &TRACK_FILTER,
-        &Some(OsString::from("./tests/trim")),
+        &PlanOptions {
+            output_dir: Some(OsString::from("./tests/trim")),
+            ..Default::default()
+        },
     );
 
     let audio_files = get_audio_files("./tests/trim");
@@ -69,7 +75,10 @@ fn append() {
         &ref_mediainfo,
         &OperationMode::Export,
         &TRACK_FILTER,
-        &Some(OsString::from("./tests/append")),
+        &PlanOptions {
+            output_dir: Some(OsString::from("./tests/append")),
+            ..Default::default()
+        },
     );
 
     let audio_files = get_audio_files("./tests");
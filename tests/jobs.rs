@@ -0,0 +1,18 @@
+use std::{thread::sleep, time::Duration};
+
+use mkvaudur::jobs::run_ordered;
+
+#[test]
+fn results_keep_input_order() {
+    let mut results = vec![];
+    run_ordered(
+        4,
+        vec![30, 0, 20, 10, 0],
+        |delay| {
+            sleep(Duration::from_millis(delay));
+            delay
+        },
+        |delay| results.push(delay),
+    );
+    assert_eq!(results, vec![30, 0, 20, 10, 0]);
+}
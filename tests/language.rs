@@ -0,0 +1,40 @@
+use mkvaudur::language::{normalize_language, LanguageFilter};
+
+#[test]
+fn normalize() {
+    for language in [
+        "de", "ger", "deu", "DE", "de-AT", "de_CH", "German", "german",
+    ] {
+        assert_eq!(normalize_language(Some(language)), "ger", "{}", language);
+    }
+    assert_eq!(normalize_language(Some("en-US")), "eng");
+    assert_eq!(normalize_language(Some("zh-Hans-CN")), "chi");
+    assert_eq!(normalize_language(Some("Norwegian Bokmål")), "nob");
+    assert_eq!(normalize_language(Some("und")), "und");
+    assert_eq!(normalize_language(Some("")), "und");
+    assert_eq!(normalize_language(None), "und");
+    // Codes without a two-letter code are kept
+    assert_eq!(normalize_language(Some("YUE")), "yue");
+}
+
+#[test]
+fn filter() {
+    let filter: LanguageFilter = "jpn, en-US".parse().unwrap();
+    assert!(filter.matches(Some("ja")));
+    assert!(filter.matches(Some("English")));
+    assert!(!filter.matches(Some("fre")));
+    assert!(!filter.matches(None));
+
+    let filter: LanguageFilter = "!eng".parse().unwrap();
+    assert!(!filter.matches(Some("en")));
+    assert!(filter.matches(Some("fr")));
+    assert!(filter.matches(None));
+
+    let filter: LanguageFilter = "und,!eng".parse().unwrap();
+    assert!(filter.matches(None));
+    assert!(!filter.matches(Some("eng")));
+    assert!(!filter.matches(Some("fr")));
+
+    assert!("eng,".parse::<LanguageFilter>().is_err());
+    assert!("!".parse::<LanguageFilter>().is_err());
+}
@@ -0,0 +1,263 @@
+mod common;
+
+use std::{ffi::OsString, path::PathBuf};
+
+use serde_json::{json, Value};
+
+use common::{mediainfo_fixture, plan_fixture, TEST_VIDEO};
+use mkvaudur::{
+    export::{
+        output::{get_metadata_args, Container, LargePcm, OutputFormat},
+        single_pass::shares_input,
+    },
+    plan::{
+        detect_speed_change, plan_file, ExportPlan, PlanOptions, Strategy, TailCheck, TrackAction,
+    },
+    TrackFilter,
+};
+
+/// A trimmed FLAC track, a padded Opus track and an Opus track within the
+/// 0.1 s treshold
+fn mixed_tracks() -> Value {
+    mediainfo_fixture(&[
+        json!({
+            "Format": "FLAC", "Compression_Mode": "Lossless", "Language": "en",
+            "Duration": "5.000"
+        }),
+        json!({
+            "Format": "Opus", "Compression_Mode": "Lossy", "Language": "ja",
+            "Duration": "1.500"
+        }),
+        json!({ "Format": "Opus", "Compression_Mode": "Lossy", "Duration": "2.010" }),
+    ])
+}
+
+#[test]
+fn plan_actions() {
+    let file_plan = plan_fixture(
+        &mixed_tracks(),
+        PlanOptions {
+            output_dir: Some(OsString::from("./tests/plan")),
+            ..Default::default()
+        },
+    );
+
+    let actions: Vec<(TrackAction, Strategy)> = file_plan
+        .tracks
+        .iter()
+        .map(|t| (t.action, t.strategy))
+        .collect();
+    assert_eq!(
+        actions,
+        vec![
+            (TrackAction::Trim, Strategy::Cut),
+            (TrackAction::Pad, Strategy::Concat),
+            (TrackAction::Copy, Strategy::Copy),
+        ]
+    );
+    assert_eq!(file_plan.tracks[0].target_duration, 2.0);
+    // Concatenating needs temporary files, so that track gets its own process
+    let shared: Vec<bool> = file_plan.tracks.iter().map(shares_input).collect();
+    assert_eq!(shared, vec![true, false, true]);
+    assert_eq!(
+        file_plan.tracks[1].output,
+        PathBuf::from("./tests/plan/test_video_2s_Audio02.JPN.opus")
+    );
+}
+
+#[test]
+fn plan_json_roundtrip() {
+    let mediainfo = mixed_tracks();
+    let track_filter = TrackFilter {
+        treshold: 0.0,
+        language: Some("en".parse().unwrap()),
+        process_all: false,
+        selection: None,
+    };
+    let plan = ExportPlan {
+        files: vec![plan_file(
+            &PathBuf::from(TEST_VIDEO),
+            &mediainfo,
+            &mediainfo,
+            &track_filter,
+            &PlanOptions {
+                tail_check: TailCheck::Off,
+                ..Default::default()
+            },
+        )],
+    };
+
+    let plan_json = serde_json::to_string(&plan).unwrap();
+    let parsed: ExportPlan = serde_json::from_str(&plan_json).unwrap();
+    assert_eq!(parsed.files[0].tracks.len(), 1);
+    assert_eq!(parsed.files[0].tracks[0].action, TrackAction::Trim);
+    assert_eq!(serde_json::to_string(&parsed).unwrap(), plan_json);
+}
+
+#[test]
+fn plan_validation() {
+    let file_plan = plan_fixture(&mixed_tracks(), PlanOptions::default());
+    let plan = ExportPlan {
+        files: vec![file_plan],
+    };
+    assert!(plan.validate().is_ok());
+
+    // A trimmed track edited to be padded would get a negative pad duration
+    let mut edited = plan.clone();
+    edited.files[0].tracks[0].strategy = Strategy::Apad;
+    assert!(edited.validate().is_err());
+    edited.files[0].tracks[0].action = TrackAction::Pad;
+    assert!(edited.validate().unwrap_err().contains("not longer"));
+
+    let mut edited = plan.clone();
+    edited.files[0].tracks[1].target_duration = -1.0;
+    assert!(edited.validate().is_err());
+
+    let mut edited = plan.clone();
+    edited.files[0].tracks[2].strategy = Strategy::Cut;
+    assert!(edited.validate().is_err());
+}
+
+#[test]
+fn detect_pal_speed_up() {
+    let video_duration = 2580.0;
+    let pal_duration = video_duration * (24000.0 / 1001.0) / 25.0;
+
+    let speed_change = detect_speed_change(pal_duration, video_duration).unwrap();
+    assert_eq!(speed_change.audio_fps, 25.0);
+    assert!((pal_duration / speed_change.tempo() - video_duration).abs() < 0.001);
+
+    assert!(detect_speed_change(video_duration + 0.5, video_duration).is_none());
+    assert_eq!(
+        detect_speed_change(video_duration * 1.001, video_duration)
+            .unwrap()
+            .video_fps,
+        24.0
+    );
+    // Short tracks differ too little for their ratio to mean anything
+    assert!(detect_speed_change(2.0 * 1.001, 2.0).is_none());
+    assert!(detect_speed_change(20.0 * 25.0 / 24.0, 20.0).is_none());
+}
+
+#[test]
+fn large_pcm_container() {
+    // Two hours of 7.1 24-bit 48 kHz PCM are about 9.3 GiB
+    let mediainfo = json!({
+        "media": {
+            "@ref": "./tests/film.mkv",
+            "track": [
+                { "@type": "General" },
+                { "@type": "Video", "ID": "1", "Duration": "7200.000" },
+                {
+                    "@type": "Audio", "ID": "2", "Format": "PCM",
+                    "Compression_Mode": "Lossless", "Duration": "7200.500",
+                    "Channels": "8", "ChannelLayout": "L R C LFE Ls Rs Lb Rb",
+                    "SamplingRate": "48000", "BitDepth": "24"
+                }
+            ]
+        }
+    });
+    let track_filter = TrackFilter {
+        treshold: 0.0,
+        language: None,
+        process_all: false,
+        selection: None,
+    };
+    let plan_options = PlanOptions {
+        tail_check: TailCheck::Off,
+        large_pcm: LargePcm::W64,
+        ..Default::default()
+    };
+
+    let file_plan = plan_file(
+        &PathBuf::from("./tests/film.mkv"),
+        &mediainfo,
+        &mediainfo,
+        &track_filter,
+        &plan_options,
+    );
+    assert_eq!(file_plan.tracks[0].container, Container::Wave64);
+    assert_eq!(
+        file_plan.tracks[0].output,
+        PathBuf::from("./tests/film_Audio01.UND.w64")
+    );
+}
+
+#[test]
+fn merged_matroska_output() {
+    let file_plan = plan_fixture(
+        &mixed_tracks(),
+        PlanOptions {
+            output_format: OutputFormat::MkaMulti,
+            ..Default::default()
+        },
+    );
+
+    assert_eq!(
+        file_plan.merged_output,
+        Some(PathBuf::from("./tests/test_video_2s_Audio.mka"))
+    );
+    assert!(file_plan
+        .tracks
+        .iter()
+        .all(|t| t.container == Container::Matroska && t.output.extension().unwrap() == "mka"));
+    assert_eq!(
+        get_metadata_args(&file_plan.tracks[1]),
+        vec![
+            "-map_metadata",
+            "-1",
+            "-metadata:s:a:0",
+            "language=ja",
+            "-disposition:a:0",
+            "0"
+        ]
+    );
+}
+
+#[test]
+fn track_metadata() {
+    let mut file_plan = plan_fixture(
+        &mixed_tracks(),
+        PlanOptions {
+            tag_changes: true,
+            ..Default::default()
+        },
+    );
+
+    // FLAC keeps its tags in the file, not the stream
+    assert_eq!(
+        get_metadata_args(&file_plan.tracks[0]),
+        vec![
+            "-map_metadata",
+            "-1",
+            "-metadata",
+            "language=en",
+            "-metadata",
+            "MKVAUDUR=trimmed from 5.000 s to 2.000 s"
+        ]
+    );
+
+    let opus_plan = &mut file_plan.tracks[1];
+    opus_plan.track.encoder = Some("libopus 1.3".to_owned());
+    opus_plan.track.commentary = true;
+    assert_eq!(
+        get_metadata_args(opus_plan),
+        vec![
+            "-map_metadata",
+            "-1",
+            "-metadata:s:a:0",
+            "language=ja",
+            "-metadata:s:a:0",
+            "encoder=libopus 1.3",
+            "-metadata:s:a:0",
+            "MKVAUDUR=padded from 1.500 s to 2.000 s"
+        ]
+    );
+    opus_plan.container = Container::Matroska;
+    assert_eq!(
+        get_metadata_args(opus_plan)[8..],
+        ["-disposition:a:0", "comment"]
+    );
+
+    assert_eq!(file_plan.tracks[2].change_tag, None);
+}
@@ -0,0 +1,104 @@
+use mkvaudur::{mediainfo::AudioTrack, select::Selection};
+
+fn audio_track(typeorder: i64, format: &str, language: Option<&str>, channels: i64) -> AudioTrack {
+    AudioTrack {
+        id: typeorder + 1,
+        typeorder,
+        format: format.to_owned(),
+        language: language.map(str::to_owned),
+        duration: 2.0,
+        channels: Some(channels),
+        sampling_rate: Some(48000),
+        ..Default::default()
+    }
+}
+
+fn selects(expression: &str, track: &AudioTrack, duration_difference: f64) -> bool {
+    expression
+        .parse::<Selection>()
+        .unwrap()
+        .matches(track, duration_difference)
+}
+
+#[test]
+fn example() {
+    let expression =
+        r#"lang in (jpn, eng) and codec != "AC-3" and channels >= 6 and not commentary"#;
+    let mut track = audio_track(1, "E-AC-3", Some("jpn"), 6);
+    assert!(selects(expression, &track, 0.0));
+
+    track.commentary = true;
+    assert!(!selects(expression, &track, 0.0));
+    track.commentary = false;
+    track.format = "AC-3".to_owned();
+    assert!(!selects(expression, &track, 0.0));
+    track.format = "DTS".to_owned();
+    track.channels = Some(2);
+    assert!(!selects(expression, &track, 0.0));
+    track.channels = Some(8);
+    track.language = Some("ger".to_owned());
+    assert!(!selects(expression, &track, 0.0));
+}
+
+#[test]
+fn fields() {
+    let mut track = audio_track(2, "FLAC", None, 2);
+    track.title = Some("Director's Commentary".to_owned());
+    track.default = true;
+
+    assert!(selects("typeorder = 2 and id == 3", &track, 0.0));
+    assert!(selects("lang = und", &track, 0.0));
+    assert!(selects("codec = flac", &track, 0.0));
+    assert!(selects(r#"title ~ "(?i)commentary""#, &track, 0.0));
+    assert!(!selects("title !~ Director", &track, 0.0));
+    assert!(selects("default and not forced", &track, 0.0));
+    assert!(selects("diff > 0.5 or diff < -0.5", &track, -1.0));
+    assert!(!selects("diff > 0.5 or diff < -0.5", &track, 0.2));
+    assert!(selects(
+        "duration <= 2 and sampling_rate = 48000",
+        &track,
+        0.0
+    ));
+    // Missing values only satisfy !=
+    assert!(!selects("bit_depth > 0 or profile = x", &track, 0.0));
+    assert!(selects("bit_depth != 16", &track, 0.0));
+}
+
+#[test]
+fn precedence() {
+    let track = audio_track(1, "Opus", Some("eng"), 2);
+    assert!(selects(
+        "forced and channels = 6 or lang = eng",
+        &track,
+        0.0
+    ));
+    assert!(!selects(
+        "forced and (channels = 6 or lang = eng)",
+        &track,
+        0.0
+    ));
+    assert!(selects("not forced and not (channels = 6)", &track, 0.0));
+}
+
+#[test]
+fn invalid() {
+    for expression in [
+        "",
+        "lang",
+        "speed > 1",
+        "channels >= many",
+        "codec < AC-3",
+        "channels ~ 2",
+        "lang in (jpn, eng",
+        "(default",
+        "default forced",
+        r#"title = "unterminated"#,
+        "title ~ (",
+    ] {
+        assert!(
+            expression.parse::<Selection>().is_err(),
+            "{} should not parse",
+            expression
+        );
+    }
+}
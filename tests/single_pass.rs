@@ -0,0 +1,104 @@
+use std::path::PathBuf;
+
+use mkvaudur::{
+    codec::LosslessPolicy,
+    export::{output::Container, single_pass::output_args},
+    mediainfo::AudioTrack,
+    plan::{Strategy, TrackAction, TrackPlan},
+};
+
+fn track_plan(typeorder: i64, format: &str, strategy: Strategy, target_duration: f64) -> TrackPlan {
+    TrackPlan {
+        track: AudioTrack {
+            id: typeorder + 1,
+            typeorder,
+            format: format.to_owned(),
+            compression_mode: Some("Lossless".to_owned()).filter(|_| format == "FLAC"),
+            duration: 10.0,
+            channels: Some(2),
+            sampling_rate: Some(48000),
+            ..Default::default()
+        },
+        action: match strategy {
+            Strategy::Cut => TrackAction::Trim,
+            Strategy::Apad => TrackAction::Pad,
+            _ => TrackAction::Copy,
+        },
+        strategy,
+        target_duration,
+        output: PathBuf::from(format!("out/track{}", typeorder)),
+        tail_peak: None,
+        speed_change: None,
+        lossless_policy: LosslessPolicy::default(),
+        container: Container::Elementary,
+        change_tag: None,
+    }
+}
+
+#[test]
+fn single_pass_output_args() {
+    let cases = [
+        (
+            track_plan(1, "AC-3", Strategy::Copy, 10.0),
+            vec![
+                "-map",
+                "0:a:0",
+                "-map_chapters",
+                "-1",
+                "-c:a",
+                "copy",
+                "-map_metadata",
+                "-1",
+                "-f",
+                "ac3",
+                "out/track1",
+            ],
+        ),
+        (
+            track_plan(2, "AC-3", Strategy::Cut, 8.0),
+            vec![
+                "-map",
+                "0:a:1",
+                "-map_chapters",
+                "-1",
+                "-t",
+                "8",
+                "-c:a",
+                "copy",
+                "-map_metadata",
+                "-1",
+                "-f",
+                "ac3",
+                "out/track2",
+            ],
+        ),
+        (
+            track_plan(3, "FLAC", Strategy::Apad, 12.5),
+            vec![
+                "-map",
+                "0:a:2",
+                "-map_chapters",
+                "-1",
+                "-af",
+                "apad=pad_dur=2.5",
+                "-c:a",
+                "flac",
+                "-strict",
+                "experimental",
+                "-map_metadata",
+                "-1",
+                "-f",
+                "flac",
+                "out/track3",
+            ],
+        ),
+    ];
+    for (track_plan, expected) in cases {
+        assert_eq!(
+            output_args(&track_plan),
+            expected,
+            "{:?}",
+            track_plan.strategy
+        );
+    }
+}
@@ -0,0 +1,83 @@
+mod common;
+
+use std::path::Path;
+
+use serde_json::json;
+
+use common::{mediainfo_fixture, plan_fixture};
+use mkvaudur::{
+    export::{
+        export_files,
+        space::{check_free_space, estimate_output_size, estimate_temp_size},
+        ExportOptions,
+    },
+    plan::{FilePlan, PlanOptions},
+};
+
+/// Plan with a trimmed Opus track of `stream_size` bytes and a padded PCM track
+fn sized_plan(output_dir: &Path, stream_size: &str) -> FilePlan {
+    plan_fixture(
+        &mediainfo_fixture(&[
+            json!({
+                "Format": "Opus", "Compression_Mode": "Lossy", "Duration": "4.000",
+                "StreamSize": stream_size
+            }),
+            json!({
+                "Format": "PCM", "Compression_Mode": "Lossless", "Duration": "1.000",
+                "Channels": "2", "SamplingRate": "48000", "BitDepth": "24", "StreamSize": "288000"
+            }),
+        ]),
+        PlanOptions {
+            output_dir: Some(output_dir.as_os_str().to_owned()),
+            ..Default::default()
+        },
+    )
+}
+
+#[test]
+fn estimates() {
+    let output_dir = tempfile::tempdir().unwrap();
+    let file_plan = sized_plan(output_dir.path(), "4000");
+
+    // Half of the Opus track remains, the PCM track doubles
+    let output_sizes: Vec<Option<u64>> =
+        file_plan.tracks.iter().map(estimate_output_size).collect();
+    assert_eq!(output_sizes, vec![Some(2000), Some(576000)]);
+    let temp_sizes: Vec<Option<u64>> = file_plan.tracks.iter().map(estimate_temp_size).collect();
+    assert_eq!(temp_sizes, vec![Some(0), Some(0)]);
+}
+
+#[test]
+fn free_space() {
+    let output_dir = tempfile::tempdir().unwrap();
+    let temp_dir = std::env::temp_dir();
+
+    let file_plans = [sized_plan(output_dir.path(), "4000")];
+    assert!(check_free_space(&file_plans, &temp_dir, 1, |_, _| false).is_ok());
+
+    let file_plans = [sized_plan(output_dir.path(), "4000000000000000000")];
+    let error = check_free_space(&file_plans, &temp_dir, 1, |_, _| false).unwrap_err();
+    assert!(error.starts_with("Not enough free space"));
+    assert!(check_free_space(&file_plans, &temp_dir, 1, |_, _| true).is_ok());
+}
+
+#[test]
+fn missing_temp_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    let temp_dir = dir.path().join("temp");
+    let options = ExportOptions {
+        temp_dir: Some(temp_dir.clone()),
+        ..Default::default()
+    };
+    assert!(export_files(&[], &options, |_| {}).is_ok());
+    assert!(temp_dir.is_dir());
+
+    let file = dir.path().join("file");
+    std::fs::write(&file, "").unwrap();
+    let options = ExportOptions {
+        temp_dir: Some(file.join("temp")),
+        ..Default::default()
+    };
+    let error = export_files(&[], &options, |_| {}).unwrap_err();
+    assert!(error.starts_with("Unable to create temp directory"));
+}
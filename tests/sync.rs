@@ -0,0 +1,96 @@
+use mkvaudur::{
+    mediainfo::AudioTrack,
+    sync::{correlate_windows, SyncResult},
+};
+
+const SAMPLE_RATE: u32 = 4000;
+
+/// Deterministic white noise
+fn noise(len: usize) -> Vec<i16> {
+    let mut state: u32 = 12345;
+    (0..len)
+        .map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            ((state >> 16) as i16) / 4
+        })
+        .collect()
+}
+
+fn audio_track(duration: f64) -> AudioTrack {
+    AudioTrack {
+        id: 1,
+        typeorder: 1,
+        format: "PCM".to_owned(),
+        compression_mode: Some("Lossless".to_owned()),
+        duration,
+        channels: Some(1),
+        sampling_rate: Some(SAMPLE_RATE as i64),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn detects_delayed_track() {
+    let reference = noise(SAMPLE_RATE as usize * 120);
+    // The track starts with 0.25 seconds of silence and is otherwise identical
+    let delay = SAMPLE_RATE as usize / 4;
+    let mut track = vec![0; delay];
+    track.extend_from_slice(&reference[..reference.len() - delay]);
+
+    let windows = correlate_windows(&track, &reference, SAMPLE_RATE, 2.0);
+    assert!(!windows.is_empty());
+    for window in &windows {
+        assert!((window.offset - 0.25).abs() < 0.001);
+        assert!(window.confidence > 0.9);
+    }
+
+    // A constant offset is no reason to stretch the track
+    let result = SyncResult {
+        track: audio_track(120.0),
+        reference_track: audio_track(120.0),
+        windows,
+        error: None,
+    };
+    assert_eq!(result.drift_tempo(), None);
+}
+
+#[test]
+fn estimates_linear_drift() {
+    // Low-pass filtered noise, like real audio at this sample rate, so a
+    // window still correlates when it is stretched by a few samples
+    let reference: Vec<i16> = noise(SAMPLE_RATE as usize * 300)
+        .windows(16)
+        .map(|w| w.iter().map(|s| *s as i32).sum::<i32>() as i16 / 4)
+        .collect();
+    // Every minute the track lags a further 20ms behind the reference
+    let track: Vec<i16> = (0..reference.len())
+        .map(|i| {
+            let source = i as f64 * (1.0 - 0.02 / 60.0);
+            reference[source as usize]
+        })
+        .collect();
+
+    let result = SyncResult {
+        track: audio_track(300.0),
+        reference_track: audio_track(300.0),
+        windows: correlate_windows(&track, &reference, SAMPLE_RATE, 2.0),
+        error: None,
+    };
+    assert!(result.has_drift());
+    let drift = result.linear_drift().unwrap();
+    assert!((drift.rate * 60.0 - 0.02).abs() < 0.002);
+    assert!(drift.initial_offset.abs() < 0.01);
+    // The track plays 59.98 s of the reference per minute
+    let tempo = result.drift_tempo().unwrap();
+    assert!((tempo - 60.0 / 59.98).abs() < 0.00004);
+}
+
+#[test]
+fn short_tracks() {
+    // Shorter than one window, which must not be sliced past its end
+    let track = noise(SAMPLE_RATE as usize * 5);
+    let reference = noise(SAMPLE_RATE as usize * 60);
+    assert!(correlate_windows(&track, &reference, SAMPLE_RATE, 0.0).is_empty());
+    assert!(correlate_windows(&reference, &track, SAMPLE_RATE, 0.0).is_empty());
+    assert!(correlate_windows(&track, &reference, SAMPLE_RATE, 2.0).is_empty());
+}
@@ -0,0 +1,57 @@
+use std::path::{Path, PathBuf};
+
+use mkvaudur::{export::template::NameTemplate, mediainfo::AudioTrack};
+
+fn audio_track() -> AudioTrack {
+    AudioTrack {
+        id: 3,
+        typeorder: 2,
+        format: "FLAC".to_owned(),
+        compression_mode: Some("Lossless".to_owned()),
+        language: Some("eng".to_owned()),
+        title: Some("Director's Cut: 5.1/Surround".to_owned()),
+        default: true,
+        duration: 5400.0,
+        channels: Some(6),
+        channel_layout: Some("L R C LFE Ls Rs".to_owned()),
+        sampling_rate: Some(48000),
+        bit_depth: Some(24),
+        ..Default::default()
+    }
+}
+
+fn render(template: &str) -> PathBuf {
+    template.parse::<NameTemplate>().unwrap().render(
+        Path::new("/media/Movie.mkv"),
+        &audio_track(),
+        ".flac",
+    )
+}
+
+#[test]
+fn default_template() {
+    assert_eq!(
+        NameTemplate::default().render(Path::new("/media/Movie.mkv"), &audio_track(), ".flac"),
+        PathBuf::from("Movie_Audio02.ENG.flac")
+    );
+}
+
+#[test]
+fn placeholders() {
+    assert_eq!(
+        render("{stem}.{lang}.{layout}.{default}.{forced}{ext}"),
+        PathBuf::from("Movie.eng.5.1.default.flac")
+    );
+    assert_eq!(
+        render("{stem}/{id:03} {title}{ext}"),
+        PathBuf::from("Movie/003 Director's Cut_ 5.1_Surround.flac")
+    );
+    assert_eq!(render("../{stem}{ext}"), PathBuf::from("Movie.flac"));
+}
+
+#[test]
+fn invalid_templates() {
+    assert!("{stem".parse::<NameTemplate>().is_err());
+    assert!("{language}".parse::<NameTemplate>().is_err());
+    assert!("{id:x}".parse::<NameTemplate>().is_err());
+}